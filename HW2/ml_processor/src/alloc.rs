@@ -0,0 +1,65 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting live heap bytes to measure peak memory.
+/// Install it with `#[global_allocator]` for [`reset_peak_memory`] and
+/// [`peak_memory`] to work.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            CURRENT.fetch_sub(layout.size(), Relaxed);
+            add(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn add(size: usize) {
+    let current = CURRENT.fetch_add(size, Relaxed) + size;
+    PEAK.fetch_max(current, Relaxed);
+}
+
+/// Restart peak tracking from the current usage, which is returned.
+pub fn reset_peak_memory() -> usize {
+    let current = CURRENT.load(Relaxed);
+    PEAK.store(current, Relaxed);
+    current
+}
+
+/// Peak heap bytes since the last [`reset_peak_memory`],
+/// or `None` if [`CountingAllocator`] is not installed.
+pub fn peak_memory() -> Option<usize> {
+    match PEAK.load(Relaxed) {
+        0 => None,
+        peak => Some(peak),
+    }
+}
@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+
+use serde::Serialize;
+
+use super::*;
+
+/// Joins the values of composite items, e.g. "artist – track".
+pub const ITEM_SEPARATOR: &str = " – ";
+/// Each playlist is a transaction.
+pub const DEFAULT_TRANSACTION_COLUMN: &str = "pid";
+/// Each song is an item, so that songs sharing a title are not merged.
+pub const DEFAULT_ITEM_COLUMN: &str = "track_uri";
+
+/// The dataset columns that group items into transactions and make up items.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Columns {
+    pub transaction: String,
+    /// Multiple columns make a composite item joined by [`ITEM_SEPARATOR`].
+    pub items: Vec<String>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            transaction: DEFAULT_TRANSACTION_COLUMN.into(),
+            items: vec![DEFAULT_ITEM_COLUMN.into()],
+        }
+    }
+}
+
+impl Columns {
+    /// `items` is a comma-separated list of column names.
+    pub fn new(transaction: &str, items: &str) -> Result<Self> {
+        let transaction = transaction.trim();
+        let items: Vec<String> = items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(Into::into)
+            .collect();
+        if transaction.is_empty() {
+            bail!("The transaction column is empty.");
+        }
+        if items.is_empty() {
+            bail!("No item column is given.");
+        }
+        if let Some(name) = items
+            .iter()
+            .map(String::as_str)
+            .chain([transaction])
+            .find(|name| name.contains(char::is_whitespace))
+        {
+            bail!("Column name `{name}` contains whitespace.");
+        }
+        Ok(Self {
+            transaction: transaction.into(),
+            items,
+        })
+    }
+
+    pub fn indices_in_header(&self, header: &str) -> Result<ColumnIndices> {
+        let attributes: Vec<&str> = header.split(',').collect();
+        let index_of = |column: &str| {
+            attributes
+                .iter()
+                .position(|attribute| *attribute == column)
+                .with_context(|| format!("Dataset file has no `{column}` column."))
+        };
+        Ok(ColumnIndices {
+            transaction: index_of(&self.transaction)?,
+            items: self
+                .items
+                .iter()
+                .map(|column| index_of(column))
+                .collect::<Result<_>>()?,
+            name: index_of(NAME_COLUMN).ok(),
+            artist: index_of(ARTIST_COLUMN).ok(),
+        })
+    }
+}
+
+/// Column for the song catalog's display name, if present in the dataset.
+pub const NAME_COLUMN: &str = "track_name";
+/// Column for the song catalog's artist, if present in the dataset.
+pub const ARTIST_COLUMN: &str = "artist_name";
+
+pub struct ColumnIndices {
+    transaction: usize,
+    items: Vec<usize>,
+    name: Option<usize>,
+    artist: Option<usize>,
+}
+
+pub struct ParsedLine<'a> {
+    pub transaction: &'a str,
+    pub item: Cow<'a, str>,
+    pub name: Option<&'a str>,
+    pub artist: Option<&'a str>,
+}
+
+impl ColumnIndices {
+    /// Get the transaction ID, the item, and its display information in `line`.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Result<ParsedLine<'a>> {
+        let attributes: Vec<&str> = line.split(',').collect();
+        let attribute = |index: usize| {
+            attributes
+                .get(index)
+                .copied()
+                .with_context(|| format!("Line does not contain column #{index}: `{line}`"))
+        };
+
+        let transaction = attribute(self.transaction)?;
+        let item = match self.items.as_slice() {
+            [index] => Cow::Borrowed(attribute(*index)?),
+            indices => Cow::Owned(
+                indices
+                    .iter()
+                    .map(|index| attribute(*index))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(ITEM_SEPARATOR),
+            ),
+        };
+        Ok(ParsedLine {
+            transaction,
+            item,
+            name: self.name.and_then(|index| attributes.get(index).copied()),
+            artist: self.artist.and_then(|index| attributes.get(index).copied()),
+        })
+    }
+}
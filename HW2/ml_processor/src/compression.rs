@@ -0,0 +1,117 @@
+use std::{
+    ffi::OsStr,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+};
+
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+
+use super::*;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZIP_MAGIC: &[u8] = &[0x50, 0x4b, 0x03, 0x04];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Zip,
+}
+
+impl Compression {
+    /// Guess the compression from the file extension, e.g. `.csv.gz`.
+    pub fn from_extension(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension().and_then(OsStr::to_str)?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "zip" => Some(Self::Zip),
+            "csv" | "txt" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    /// Guess the compression from the first bytes of the file.
+    pub fn from_magic_bytes(header: &[u8]) -> Self {
+        if header.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Self::Zstd
+        } else if header.starts_with(ZIP_MAGIC) {
+            Self::Zip
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Pass a reader of the dataset file to `read`,
+/// decompressing it on the fly if it is compressed,
+/// so that the decompressed file is never held in memory as a whole.
+/// The extension takes precedence; the magic bytes are used otherwise.
+pub fn read_decompressed<T>(
+    path: impl AsRef<Path>,
+    read: impl FnOnce(&mut dyn BufRead) -> Result<T>,
+) -> Result<T> {
+    let path = path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("Failed to open `{path:?}`"))?;
+    let compression = match Compression::from_extension(path) {
+        Some(compression) => compression,
+        None => {
+            let mut header = [0; 4];
+            let n_read = file.read(&mut header)?;
+            file.seek(SeekFrom::Start(0))?;
+            Compression::from_magic_bytes(&header[..n_read])
+        }
+    };
+    debug!(
+        "Reading `{}` with compression {compression:?}.",
+        path.display()
+    );
+
+    match compression {
+        Compression::None => read(&mut BufReader::new(file)),
+        Compression::Gzip => read(&mut BufReader::new(MultiGzDecoder::new(BufReader::new(
+            file,
+        ))))
+        .context("Failed to read gzip dataset"),
+        Compression::Zstd => read(&mut BufReader::new(zstd::Decoder::new(file)?))
+            .context("Failed to read zstd dataset"),
+        Compression::Zip => read_zip_csv_member(file, read),
+    }
+}
+
+/// Pass a reader of the CSV member of the zip archive to `read`.
+/// The archive must contain exactly one `.csv` file,
+/// or exactly one file of any name.
+fn read_zip_csv_member<T>(
+    file: File,
+    read: impl FnOnce(&mut dyn BufRead) -> Result<T>,
+) -> Result<T> {
+    let mut archive =
+        ZipArchive::new(BufReader::new(file)).context("Failed to open zip archive")?;
+    let file_names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(Into::into)
+        .collect();
+    let csv_names: Vec<&String> = file_names
+        .iter()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".csv"))
+        .collect();
+
+    let member_name = match (csv_names.as_slice(), file_names.as_slice()) {
+        ([csv_name], _) => *csv_name,
+        ([], [only_name]) => only_name,
+        ([], _) => bail!("Zip archive has no CSV member: {file_names:?}."),
+        _ => bail!("Zip archive has multiple CSV members: {csv_names:?}."),
+    };
+    debug!("Reading `{member_name}` from zip archive.");
+
+    let member = archive.by_name(member_name)?;
+    let result = read(&mut BufReader::new(member))
+        .with_context(|| format!("Failed to read `{member_name}` in zip archive"));
+    result
+}
@@ -0,0 +1,112 @@
+use std::{
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+
+use super::*;
+
+/// How often the dataset URL file is checked for a new URL.
+const URL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the daemon gets the dataset URL from.
+#[derive(Clone, Debug)]
+pub enum UrlSource {
+    Fixed(String),
+    /// A local file whose trimmed content is the URL.
+    File(PathBuf),
+}
+
+impl UrlSource {
+    fn current_url(&self) -> Result<String> {
+        match self {
+            Self::Fixed(url) => Ok(url.clone()),
+            Self::File(path) => {
+                let url = read_file(path)
+                    .with_context(|| format!("Failed to read dataset URL file `{path:?}`"))?;
+                let url = url.trim();
+                if url.is_empty() {
+                    bail!("Dataset URL file `{path:?}` is empty.");
+                }
+                Ok(url.into())
+            }
+        }
+    }
+}
+
+/// Long-running mode re-running every `interval`,
+/// or as soon as the URL source yields a new URL.
+/// A new URL only re-mines if the checkpoint no longer matches,
+/// while the same URL is downloaded again and re-mined if its content changed.
+pub struct Daemon {
+    pub url_source: UrlSource,
+    pub data_dir: PathBuf,
+    pub columns: Columns,
+    /// Must be positive.
+    pub interval: Duration,
+    pub status: Status,
+}
+
+/// When the [`Daemon`] last ran, and with which URL.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    last_url: Option<String>,
+    next_check: Option<Instant>,
+}
+
+impl Daemon {
+    pub fn run(&self) -> ! {
+        info!(
+            "Running as daemon with {:?}, checking every {:?}.",
+            self.url_source, self.interval
+        );
+        let mut schedule = Schedule::default();
+        loop {
+            self.tick(
+                &mut schedule,
+                Instant::now(),
+                |url, recheck| match recheck {
+                    true => recheck_with_status(url, &self.data_dir, &self.columns, &self.status),
+                    false => run_with_status(url, &self.data_dir, &self.columns, &self.status),
+                },
+            );
+            sleep(URL_POLL_INTERVAL.min(self.interval));
+        }
+    }
+
+    /// Call `run` with the URL if it is new or `interval` passed since
+    /// the last run at `now`, and whether to recheck the same URL.
+    pub fn tick(
+        &self,
+        schedule: &mut Schedule,
+        now: Instant,
+        run: impl FnOnce(&str, bool) -> Result<(), Error>,
+    ) {
+        let url = match self.url_source.current_url() {
+            Ok(url) => url,
+            Err(why) => {
+                warn!("Failed to get the dataset URL: {why:?}");
+                return;
+            }
+        };
+        let recheck = match (&schedule.last_url, schedule.next_check) {
+            (Some(last_url), Some(next_check)) if *last_url == url => {
+                if now < next_check {
+                    return;
+                }
+                true
+            }
+            _ => false,
+        };
+        self.status.start_run(&url);
+        let result = run(&url, recheck);
+        if let Err(why) = &result {
+            error!("{} (retryable: {}).", why.chain(), why.is_retryable());
+        }
+        self.status.finish_run(&result);
+        schedule.last_url = Some(url);
+        schedule.next_check = Some(Instant::now().max(now) + self.interval);
+    }
+}
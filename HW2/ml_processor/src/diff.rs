@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use serde::Serialize;
+
+use super::*;
+
+/// How many entries of each list the human-readable report shows.
+const DISPLAY_LIMIT: usize = 10;
+
+/// Differences between an old and a new set of rules,
+/// identifying rules by their antecedent and consequent.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ModelDiff {
+    pub n_old_rules: usize,
+    pub n_new_rules: usize,
+    pub added: Vec<RuleRecord>,
+    pub removed: Vec<RuleRecord>,
+    /// Rules in both with a different confidence, largest shift first.
+    pub confidence_shifts: Vec<ConfidenceShift>,
+    /// Songs recommendable, i.e., in a consequent, only in the new rules.
+    pub gained_songs: Vec<String>,
+    /// Songs recommendable only in the old rules.
+    pub lost_songs: Vec<String>,
+    /// Fraction of all distinct rules that were added or removed.
+    pub change_ratio: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfidenceShift {
+    pub antecedent: Vec<String>,
+    pub consequent: Vec<String>,
+    pub old: f32,
+    pub new: f32,
+}
+
+impl ModelDiff {
+    pub fn new(old: &[Rule], new: &[Rule]) -> Self {
+        let old_records = records_by_key(old);
+        let new_records = records_by_key(new);
+
+        let mut added: Vec<RuleRecord> = new_records
+            .iter()
+            .filter(|(key, _)| !old_records.contains_key(*key))
+            .map(|(_, record)| record.clone())
+            .collect();
+        let mut removed: Vec<RuleRecord> = old_records
+            .iter()
+            .filter(|(key, _)| !new_records.contains_key(*key))
+            .map(|(_, record)| record.clone())
+            .collect();
+        let by_itemsets =
+            |record: &RuleRecord| (record.antecedent.clone(), record.consequent.clone());
+        added.sort_unstable_by_key(by_itemsets);
+        removed.sort_unstable_by_key(by_itemsets);
+
+        let mut confidence_shifts: Vec<ConfidenceShift> = old_records
+            .iter()
+            .filter_map(|(key, old)| {
+                let new = new_records.get(key)?;
+                (old.confidence != new.confidence).then(|| ConfidenceShift {
+                    antecedent: old.antecedent.clone(),
+                    consequent: old.consequent.clone(),
+                    old: old.confidence,
+                    new: new.confidence,
+                })
+            })
+            .collect();
+        confidence_shifts.sort_unstable_by(|a, b| {
+            b.delta()
+                .abs()
+                .total_cmp(&a.delta().abs())
+                .then_with(|| a.antecedent.cmp(&b.antecedent))
+                .then_with(|| a.consequent.cmp(&b.consequent))
+        });
+
+        let old_songs = recommendable_songs(old);
+        let new_songs = recommendable_songs(new);
+        let gained_songs = new_songs.difference(&old_songs).cloned().collect();
+        let lost_songs = old_songs.difference(&new_songs).cloned().collect();
+
+        let n_distinct = old_records.len() + added.len();
+        let change_ratio = match n_distinct {
+            0 => 0.0,
+            _ => (added.len() + removed.len()) as f32 / n_distinct as f32,
+        };
+
+        Self {
+            n_old_rules: old.len(),
+            n_new_rules: new.len(),
+            added,
+            removed,
+            confidence_shifts,
+            gained_songs,
+            lost_songs,
+            change_ratio,
+        }
+    }
+}
+
+impl ConfidenceShift {
+    pub fn delta(&self) -> f32 {
+        self.new - self.old
+    }
+}
+
+type RuleKey = (Vec<String>, Vec<String>);
+
+fn records_by_key(rules: &[Rule]) -> HashMap<RuleKey, RuleRecord> {
+    rules
+        .iter()
+        .map(|rule| {
+            let record = RuleRecord::from(rule);
+            (
+                (record.antecedent.clone(), record.consequent.clone()),
+                record,
+            )
+        })
+        .collect()
+}
+
+fn recommendable_songs(rules: &[Rule]) -> BTreeSet<String> {
+    rules
+        .iter()
+        .flat_map(|rule| rule.consequent.iter().cloned())
+        .collect()
+}
+
+impl fmt::Display for ModelDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rules: {} -> {}", self.n_old_rules, self.n_new_rules)?;
+        write!(f, "Change ratio: {:.4}", self.change_ratio)?;
+        write_list(f, "Added rules", &self.added, |f, record| {
+            write!(f, "{}", RuleDisplay(record))
+        })?;
+        write_list(f, "Removed rules", &self.removed, |f, record| {
+            write!(f, "{}", RuleDisplay(record))
+        })?;
+        write_list(
+            f,
+            "Confidence shifts",
+            &self.confidence_shifts,
+            |f, shift| {
+                write!(
+                    f,
+                    "{:?} => {:?}: {:.4} -> {:.4}",
+                    shift.antecedent, shift.consequent, shift.old, shift.new
+                )
+            },
+        )?;
+        write_list(f, "Gained songs", &self.gained_songs, |f, song| {
+            write!(f, "{song}")
+        })?;
+        write_list(f, "Lost songs", &self.lost_songs, |f, song| {
+            write!(f, "{song}")
+        })
+    }
+}
+
+struct RuleDisplay<'a>(&'a RuleRecord);
+
+impl fmt::Display for RuleDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let RuleRecord {
+            antecedent,
+            consequent,
+            confidence,
+            ..
+        } = self.0;
+        write!(f, "{antecedent:?} => {consequent:?} ({confidence:.4})")
+    }
+}
+
+fn write_list<T>(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    entries: &[T],
+    mut write_entry: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    write!(f, "\n{title}: {}", entries.len())?;
+    for entry in entries.iter().take(DISPLAY_LIMIT) {
+        write!(f, "\n    ")?;
+        write_entry(f, entry)?;
+    }
+    if entries.len() > DISPLAY_LIMIT {
+        write!(f, "\n    …")?;
+    }
+    Ok(())
+}
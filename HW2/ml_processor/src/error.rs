@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why processing a dataset failed, for supervisors to decide on retrying.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The dataset URL does not name a file to save the dataset as;
+    /// retrying does not help until the URL changes.
+    #[error("Invalid dataset URL `{url}`")]
+    Url {
+        url: String,
+        #[source]
+        source: BoxError,
+    },
+    /// Possibly transient, e.g., the network or the server is down.
+    #[error("Failed to download `{url}`")]
+    Download {
+        url: String,
+        #[source]
+        source: BoxError,
+    },
+    /// Possibly transient, e.g., the disk failed while reading the downloaded dataset.
+    #[error("Failed to read dataset {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+    /// The dataset is malformed or lacks the configured columns;
+    /// retrying does not help until the dataset or configuration changes.
+    #[error("Failed to parse dataset `{url}`")]
+    Parse {
+        url: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Failed to mine rules")]
+    Mine(#[source] BoxError),
+    /// Possibly transient, e.g., the disk is full.
+    #[error("Failed to write artifacts to {data_dir:?}")]
+    Write {
+        data_dir: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+}
+
+impl Error {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Download { .. } | Self::Read { .. } | Self::Write { .. }
+        )
+    }
+
+    /// This error and its sources, separated by colons.
+    pub fn chain(&self) -> String {
+        let mut chain = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            chain.push_str(&format!(": {error}"));
+            source = error.source();
+        }
+        chain
+    }
+
+    /// Process exit code for this error, following `sysexits.h`.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            // EX_USAGE.
+            Self::Url { .. } => 64,
+            // EX_TEMPFAIL.
+            Self::Download { .. } => 75,
+            // EX_IOERR.
+            Self::Read { .. } => 74,
+            // EX_DATAERR.
+            Self::Parse { .. } => 65,
+            // EX_SOFTWARE.
+            Self::Mine(_) => 70,
+            // EX_IOERR.
+            Self::Write { .. } => 74,
+        }
+    }
+}
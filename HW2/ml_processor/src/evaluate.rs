@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+
+use super::*;
+
+/// How to evaluate rules on hold-out transactions.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct EvaluationConfig {
+    /// Seeds both the train/test split and the choice of hidden songs.
+    pub seed: u64,
+    /// Fraction of transactions held out for testing.
+    pub test_fraction: f32,
+    /// Songs hidden from each test transaction, to be recommended back.
+    pub n_hidden: usize,
+    /// Number of recommendations considered.
+    pub k: usize,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            test_fraction: 0.2,
+            n_hidden: 1,
+            k: MAX_LENGTH,
+        }
+    }
+}
+
+/// Recommendation quality averaged over the evaluated test transactions;
+/// all zero if there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    pub precision_at_k: f32,
+    pub recall_at_k: f32,
+    /// Fraction of test transactions with at least one hidden song recommended.
+    pub hit_rate: f32,
+    /// Fraction of the training items recommended at least once.
+    pub coverage: f32,
+    /// Mean reciprocal rank of the first hidden song recommended.
+    pub mrr: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EvaluationReport {
+    pub config: EvaluationConfig,
+    pub parameters: MiningParameters,
+    pub n_train: usize,
+    pub n_test: usize,
+    /// Test transactions with too few songs to hide some and query the rest.
+    pub n_skipped: usize,
+    pub n_rules: usize,
+    pub metrics: Metrics,
+}
+
+pub struct Split<'a> {
+    pub train: Vec<Transaction>,
+    pub test: Vec<&'a Transaction>,
+}
+
+/// Shuffle the transactions with the configured seed and hold out
+/// `test_fraction` of them.
+pub fn split_transactions<'a>(
+    transactions: &'a [Transaction],
+    config: &EvaluationConfig,
+) -> Split<'a> {
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let mut indices: Vec<usize> = (0..transactions.len()).collect();
+    indices.shuffle(&mut rng);
+    let n_test = (transactions.len() as f32 * config.test_fraction).round() as usize;
+    let (test_indices, train_indices) = indices.split_at(n_test.min(transactions.len()));
+    Split {
+        train: train_indices
+            .iter()
+            .map(|&index| transactions[index].clone())
+            .collect(),
+        test: test_indices
+            .iter()
+            .map(|&index| &transactions[index])
+            .collect(),
+    }
+}
+
+/// Mine on a training split of `transactions` and evaluate on the rest.
+pub fn evaluate(
+    transactions: &[Transaction],
+    parameters: MiningParameters,
+    config: &EvaluationConfig,
+) -> EvaluationReport {
+    let Split { train, test } = split_transactions(transactions, config);
+    debug!(
+        "Evaluating with {} training and {} test transactions.",
+        train.len(),
+        test.len()
+    );
+    let mined = mine(&train, parameters);
+    let (metrics, n_skipped) = evaluate_rules(&mined, &test, config);
+    EvaluationReport {
+        config: *config,
+        parameters,
+        n_train: train.len(),
+        n_test: test.len(),
+        n_skipped,
+        n_rules: mined.rules.len(),
+        metrics,
+    }
+}
+
+/// For each test transaction, hide `n_hidden` songs, recommend from the rest
+/// the way the REST API Server does, and score the top `k` recommendations.
+/// Return the metrics and the number of skipped test transactions.
+pub fn evaluate_rules(
+    mined: &MinedRules,
+    test: &[&Transaction],
+    config: &EvaluationConfig,
+) -> (Metrics, usize) {
+    let rule_index = index_rules(mined.rules.iter().cloned());
+    let popular_items: Vec<String> = mined
+        .frequencies
+        .counts
+        .iter()
+        .map(|(item, _)| item.clone())
+        .collect();
+
+    // Offset the seed so hiding songs does not mirror the split's shuffle.
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed.wrapping_add(1));
+    let mut sums = Metrics::default();
+    let mut recommended = HashSet::new();
+    let mut n_evaluated = 0;
+    for transaction in test {
+        if transaction.len() <= config.n_hidden {
+            continue;
+        }
+        let mut items: Vec<&str> = transaction.iter().map(AsRef::as_ref).collect();
+        items.sort_unstable();
+        items.shuffle(&mut rng);
+        let (hidden, query) = items.split_at(config.n_hidden);
+
+        let recommendations = recommend(
+            query.iter().map(|item| item.to_string()).collect(),
+            &rule_index,
+            &popular_items,
+        );
+        let top_k = &recommendations[..recommendations.len().min(config.k)];
+        let hit_ranks: Vec<usize> = top_k
+            .iter()
+            .enumerate()
+            .filter(|(_, (song, _))| hidden.contains(&song.as_str()))
+            .map(|(rank, _)| rank)
+            .collect();
+
+        sums.precision_at_k += hit_ranks.len() as f32 / config.k as f32;
+        sums.recall_at_k += hit_ranks.len() as f32 / config.n_hidden as f32;
+        if let Some(first_rank) = hit_ranks.first() {
+            sums.hit_rate += 1.0;
+            sums.mrr += 1.0 / (first_rank + 1) as f32;
+        }
+        recommended.extend(top_k.iter().map(|(song, _)| song.clone()));
+        n_evaluated += 1;
+    }
+
+    let metrics = match n_evaluated {
+        0 => Metrics::default(),
+        _ => {
+            let n = n_evaluated as f32;
+            Metrics {
+                precision_at_k: sums.precision_at_k / n,
+                recall_at_k: sums.recall_at_k / n,
+                hit_rate: sums.hit_rate / n,
+                coverage: recommended.len() as f32 / popular_items.len().max(1) as f32,
+                mrr: sums.mrr / n,
+            }
+        }
+    };
+    (metrics, test.len() - n_evaluated)
+}
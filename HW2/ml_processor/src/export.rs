@@ -0,0 +1,276 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{BufRead, BufReader, Read, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use arrow_array::{
+    builder::{ListBuilder, StringBuilder},
+    ArrayRef, Float32Array, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// File formats rules can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RulesFormat {
+    JsonLines,
+    Csv,
+    Parquet,
+}
+
+impl RulesFormat {
+    pub fn from_extension(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for RulesFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            _ => bail!("Unknown rules format `{s}`, expected `jsonl`, `csv`, or `parquet`."),
+        }
+    }
+}
+
+impl fmt::Display for RulesFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::JsonLines => "jsonl",
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        })
+    }
+}
+
+/// A rule as exported, with sorted itemsets.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuleRecord {
+    pub antecedent: Vec<String>,
+    pub consequent: Vec<String>,
+    /// Fraction of transactions containing both itemsets, if known.
+    #[serde(default)]
+    pub support: Option<f32>,
+    pub confidence: f32,
+    pub lift: f32,
+}
+
+impl From<&Rule> for RuleRecord {
+    fn from(rule: &Rule) -> Self {
+        let sorted = |itemset: &HashSet<String>| {
+            let mut items: Vec<String> = itemset.iter().cloned().collect();
+            items.sort_unstable();
+            items
+        };
+        Self {
+            antecedent: sorted(&rule.antecedent),
+            consequent: sorted(&rule.consequent),
+            support: None,
+            confidence: rule.confidence,
+            lift: rule.lift,
+        }
+    }
+}
+
+impl RuleRecord {
+    /// Fill in the support from the itemset counts, if there.
+    pub fn with_support(
+        mut self,
+        counts: &HashMap<&[String], usize>,
+        n_transactions: usize,
+    ) -> Self {
+        let mut union: Vec<String> = self
+            .antecedent
+            .iter()
+            .chain(&self.consequent)
+            .cloned()
+            .collect();
+        union.sort_unstable();
+        self.support = counts
+            .get(union.as_slice())
+            .map(|count| *count as f32 / n_transactions as f32);
+        self
+    }
+}
+
+impl From<RuleRecord> for Rule {
+    fn from(record: RuleRecord) -> Self {
+        Self {
+            antecedent: record.antecedent.into_iter().collect(),
+            consequent: record.consequent.into_iter().collect(),
+            confidence: record.confidence,
+            lift: record.lift,
+        }
+    }
+}
+
+/// Export `rules`, with their support if `itemsets` are given.
+pub fn export_rules(
+    rules: &[Rule],
+    itemsets: Option<&FrequentItemsets>,
+    format: RulesFormat,
+    mut writer: impl Write + Send,
+) -> Result<()> {
+    let counts = itemsets.map(|itemsets| (itemsets.count_map(), itemsets.n_transactions));
+    let records = rules.iter().map(|rule| match &counts {
+        Some((counts, n_transactions)) => {
+            RuleRecord::from(rule).with_support(counts, *n_transactions)
+        }
+        None => RuleRecord::from(rule),
+    });
+    match format {
+        RulesFormat::JsonLines => {
+            for record in records {
+                serde_json::to_writer(&mut writer, &record)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        RulesFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(CSV_HEADER)?;
+            for record in records {
+                csv_writer.write_record([
+                    serde_json::to_string(&record.antecedent)?,
+                    serde_json::to_string(&record.consequent)?,
+                    record.support.map(|s| s.to_string()).unwrap_or_default(),
+                    record.confidence.to_string(),
+                    record.lift.to_string(),
+                ])?;
+            }
+            csv_writer.flush()?;
+        }
+        RulesFormat::Parquet => write_parquet(records, writer)?,
+    }
+    Ok(())
+}
+
+/// Read rules exported as JSON Lines or CSV, e.g., hand-curated rules.
+/// In CSV, an itemset is either a JSON array or a single item.
+pub fn import_rules(reader: impl Read, format: RulesFormat) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    match format {
+        RulesFormat::JsonLines => {
+            for (index, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let rule = serde_json::from_str::<RuleRecord>(&line)
+                    .map_err(Into::into)
+                    .and_then(|record| checked_rule(record.into()))
+                    .with_context(|| format!("Invalid rule on line {}", index + 1))?;
+                rules.push(rule);
+            }
+        }
+        RulesFormat::Csv => {
+            for (index, row) in csv::Reader::from_reader(reader)
+                .deserialize::<CsvRuleRecord>()
+                .enumerate()
+            {
+                let rule = row
+                    .map_err(Into::into)
+                    .and_then(|row| checked_rule(row.try_into()?))
+                    .with_context(|| format!("Invalid rule on row {}", index + 1))?;
+                rules.push(rule);
+            }
+        }
+        RulesFormat::Parquet => bail!("Importing rules from Parquet is not supported."),
+    }
+    Ok(rules)
+}
+
+/// Reject rules that could never be recommended from.
+fn checked_rule(rule: Rule) -> Result<Rule> {
+    if rule.antecedent.is_empty() {
+        bail!("Empty antecedent.");
+    }
+    if rule.consequent.is_empty() {
+        bail!("Empty consequent.");
+    }
+    Ok(rule)
+}
+
+const CSV_HEADER: [&str; 5] = ["antecedent", "consequent", "support", "confidence", "lift"];
+
+#[derive(Deserialize)]
+struct CsvRuleRecord {
+    antecedent: String,
+    consequent: String,
+    support: Option<f32>,
+    confidence: f32,
+    lift: f32,
+}
+
+impl TryFrom<CsvRuleRecord> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(row: CsvRuleRecord) -> Result<Self> {
+        let parse_itemset = |cell: &str| -> Result<Vec<String>> {
+            match cell.trim_start() {
+                "" => Ok(Vec::new()),
+                json if json.starts_with('[') => {
+                    serde_json::from_str(cell).with_context(|| format!("Invalid itemset `{cell}`"))
+                }
+                _ => Ok(vec![cell.into()]),
+            }
+        };
+        Ok(RuleRecord {
+            antecedent: parse_itemset(&row.antecedent)?,
+            consequent: parse_itemset(&row.consequent)?,
+            support: row.support,
+            confidence: row.confidence,
+            lift: row.lift,
+        }
+        .into())
+    }
+}
+
+fn write_parquet(
+    records: impl Iterator<Item = RuleRecord>,
+    writer: impl Write + Send,
+) -> Result<()> {
+    let mut antecedents = ListBuilder::new(StringBuilder::new());
+    let mut consequents = ListBuilder::new(StringBuilder::new());
+    let (mut supports, mut confidences, mut lifts) = (Vec::new(), Vec::new(), Vec::new());
+    for record in records {
+        antecedents.append_value(record.antecedent.into_iter().map(Some));
+        consequents.append_value(record.consequent.into_iter().map(Some));
+        supports.push(record.support);
+        confidences.push(record.confidence);
+        lifts.push(record.lift);
+    }
+
+    let itemset_type = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("antecedent", itemset_type.clone(), false),
+        Field::new("consequent", itemset_type, false),
+        Field::new("support", DataType::Float32, true),
+        Field::new("confidence", DataType::Float32, false),
+        Field::new("lift", DataType::Float32, false),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(antecedents.finish()),
+        Arc::new(consequents.finish()),
+        Arc::new(Float32Array::from(supports)),
+        Arc::new(Float32Array::from(confidences)),
+        Arc::new(Float32Array::from(lifts)),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let mut parquet_writer = ArrowWriter::try_new(writer, schema, None)?;
+    parquet_writer.write(&batch)?;
+    parquet_writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::*;
+
+/// Summary statistics of a rules file.
+#[derive(Clone, Debug)]
+pub struct RuleStats {
+    pub n_rules: usize,
+    /// Number of rules per antecedent length.
+    pub antecedent_lengths: BTreeMap<usize, usize>,
+    pub confidence: Distribution,
+    pub lift: Distribution,
+}
+
+impl RuleStats {
+    pub fn new(rules: &[Rule]) -> Self {
+        let mut antecedent_lengths = BTreeMap::new();
+        for rule in rules {
+            *antecedent_lengths.entry(rule.antecedent.len()).or_default() += 1;
+        }
+        Self {
+            n_rules: rules.len(),
+            antecedent_lengths,
+            confidence: Distribution::new(rules.iter().map(|rule| rule.confidence)),
+            lift: Distribution::new(rules.iter().map(|rule| rule.lift)),
+        }
+    }
+}
+
+impl fmt::Display for RuleStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rules: {}", self.n_rules)?;
+        writeln!(f, "Antecedent length histogram:")?;
+        for (length, count) in &self.antecedent_lengths {
+            writeln!(f, "    {length}: {count}")?;
+        }
+        writeln!(f, "Confidence: {}", self.confidence)?;
+        write!(f, "Lift: {}", self.lift)
+    }
+}
+
+/// Quantiles and mean of a metric; all zero if there are no values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Distribution {
+    pub min: f32,
+    pub p25: f32,
+    pub median: f32,
+    pub p75: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+impl Distribution {
+    pub fn new(values: impl Iterator<Item = f32>) -> Self {
+        let mut values: Vec<f32> = values.collect();
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_unstable_by(f32::total_cmp);
+        let quantile = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize];
+        Self {
+            min: values[0],
+            p25: quantile(0.25),
+            median: quantile(0.5),
+            p75: quantile(0.75),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            min,
+            p25,
+            median,
+            p75,
+            max,
+            mean,
+        } = self;
+        write!(
+            f,
+            "min {min:.4}, p25 {p25:.4}, median {median:.4}, p75 {p75:.4}, max {max:.4}, mean {mean:.4}"
+        )
+    }
+}
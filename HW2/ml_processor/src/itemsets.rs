@@ -0,0 +1,90 @@
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use super::*;
+
+/// Collect the frequent items, the multi-item `frequent` itemsets apriori
+/// found, whether or not they formed a rule, and count the transactions
+/// containing any other itemset appearing in `rules` as an antecedent,
+/// a consequent, or their union.
+/// `item_counts` are the precomputed single-item counts.
+pub fn count_itemsets<'a, T>(
+    transactions: impl Iterator<Item = &'a HashSet<T>> + Clone,
+    item_counts: &HashMap<&str, usize>,
+    frequent: &apriori::FrequentItemsets,
+    rules: &[Rule],
+    parameters: MiningParameters,
+) -> FrequentItemsets
+where
+    T: Borrow<str> + Eq + Hash + 'a,
+{
+    let n_transactions = transactions.clone().count();
+    let min_count = (parameters.min_support * n_transactions as f32).ceil() as usize;
+    let mut counts: HashMap<Vec<String>, usize> = item_counts
+        .iter()
+        .filter(|(_, count)| **count >= min_count)
+        .map(|(item, count)| (vec![item.to_string()], *count))
+        .collect();
+    for (itemset, count) in frequent.values().flatten() {
+        if itemset.len() > 1 {
+            let items = itemset.iter().map(|item| item.to_string()).collect();
+            counts.insert(items, *count as usize);
+        }
+    }
+
+    let mut candidates = HashSet::new();
+    for rule in rules {
+        let union = rule.antecedent.union(&rule.consequent).cloned().collect();
+        for itemset in [&rule.antecedent, &rule.consequent, &union] {
+            let mut items: Vec<String> = itemset.iter().cloned().collect();
+            items.sort_unstable();
+            match items.as_slice() {
+                [item] => {
+                    let count = item_counts.get(item.as_str()).copied().unwrap_or_default();
+                    counts.insert(items, count);
+                }
+                _ if counts.contains_key(&items) => {}
+                _ => _ = candidates.insert(items),
+            }
+        }
+    }
+
+    // Each candidate is only checked in transactions containing its first item.
+    let mut candidates_by_first = HashMap::<&str, Vec<&[String]>>::new();
+    for items in &candidates {
+        candidates_by_first
+            .entry(items[0].as_str())
+            .or_default()
+            .push(items);
+    }
+    let mut candidate_counts = HashMap::<&[String], usize>::new();
+    for transaction in transactions {
+        for item in transaction {
+            let Some(itemsets) = candidates_by_first.get(item.borrow()) else {
+                continue;
+            };
+            for itemset in itemsets {
+                if itemset[1..]
+                    .iter()
+                    .all(|item| transaction.contains(item.as_str()))
+                {
+                    *candidate_counts.entry(itemset).or_default() += 1;
+                }
+            }
+        }
+    }
+    for (items, count) in candidate_counts {
+        counts.insert(items.to_vec(), count);
+    }
+
+    let mut counts: Vec<(Vec<String>, usize)> = counts.into_iter().collect();
+    counts.sort_unstable_by(|(a_items, a), (b_items, b)| b.cmp(a).then(a_items.cmp(b_items)));
+    FrequentItemsets {
+        n_transactions,
+        parameters,
+        counts,
+    }
+}
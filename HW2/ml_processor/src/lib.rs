@@ -1,52 +1,237 @@
-use std::{fs::File, path::Path};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use apriori::Rule;
-use bincode::serialize_into;
 use log::{debug, warn};
 
-use checkpoint::{check_checkpoint, write_checkpoint};
+use model::new_model;
 use shared::*;
-use url_file::process_data;
+use url_file::{process_data, MiningOutput};
 
+pub use alloc::{peak_memory, reset_peak_memory, CountingAllocator};
+pub use checkpoint::check_checkpoint;
+pub use columns::{Columns, DEFAULT_ITEM_COLUMN, DEFAULT_TRANSACTION_COLUMN, ITEM_SEPARATOR};
+pub use daemon::{Daemon, Schedule, UrlSource};
+pub use diff::{ConfidenceShift, ModelDiff};
+pub use error::{BoxError, Error};
+pub use evaluate::{
+    evaluate, evaluate_rules, split_transactions, EvaluationConfig, EvaluationReport, Metrics,
+    Split,
+};
+pub use export::{export_rules, import_rules, RuleRecord, RulesFormat};
+pub use inspect::{Distribution, RuleStats};
+pub use status::{spawn_status_server, State, Status, StatusReport};
+pub use sweep::{sweep, write_sweep_csv, SweepGrid, SweepResult, SweepTable};
+pub use url_file::{
+    check_http_url, dataset_file_name, fetch, file_sha256, ingest, mine, parse_dataset, parse_file,
+    Dataset, MinedRules, Transaction, MINING_PARAMETERS,
+};
+
+mod alloc;
 mod checkpoint;
+mod columns;
+mod compression;
+mod daemon;
+mod diff;
+mod error;
+mod evaluate;
+mod export;
+mod inspect;
+mod itemsets;
+mod model;
+mod status;
+mod sweep;
 #[cfg(test)]
 mod tests;
 mod url_file;
 
-pub fn run(dataset_url: &str, data_dir: impl AsRef<Path>) -> Result<()> {
+pub fn run(dataset_url: &str, data_dir: impl AsRef<Path>, columns: &Columns) -> Result<(), Error> {
+    run_with_status(dataset_url, data_dir, columns, &Status::default())
+}
+
+/// [`run`] while reporting progress to `status`.
+pub fn run_with_status(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    columns: &Columns,
+    status: &Status,
+) -> Result<(), Error> {
+    run_checked(dataset_url, data_dir, columns, status, false)
+}
+
+/// [`run_with_status`], but even if the checkpoint is up to date,
+/// download the dataset again and re-mine if its content changed since
+/// the last mining, e.g., for a URL whose file is replaced in place.
+pub fn recheck_with_status(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    columns: &Columns,
+    status: &Status,
+) -> Result<(), Error> {
+    run_checked(dataset_url, data_dir, columns, status, true)
+}
+
+fn run_checked(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    columns: &Columns,
+    status: &Status,
+    recheck: bool,
+) -> Result<(), Error> {
     debug!(
-        "Running with dataset `{dataset_url}` at `{:?}`.",
+        "Running with dataset `{dataset_url}` at `{:?}` and columns {columns:?}.",
         data_dir.as_ref()
     );
     let checkpoint_path = checkpoint_path(&data_dir);
-    match check_checkpoint(dataset_url, &checkpoint_path) {
-        Ok(true) => {
-            debug!("Checkpoint is up to date, the ML processor is skipping processing.");
-            return Ok(());
+    let up_to_date = match check_checkpoint(dataset_url, columns, &checkpoint_path) {
+        Ok(up_to_date) => up_to_date,
+        Err(why) => {
+            warn!("Failed to check the checkpoint: {:?}", why);
+            false
         }
-        Ok(false) => {}
-        Err(why) => warn!("Failed to check the checkpoint: {:?}", why),
+    };
+    if up_to_date && !recheck {
+        debug!("Checkpoint is up to date, the ML processor is skipping processing.");
+        return Ok(());
+    }
+
+    let file_path = fetch(dataset_url, &data_dir, recheck, status)?;
+    let dataset_sha256 = file_sha256(&file_path).map_err(|source| Error::Read {
+        path: file_path.clone(),
+        source: source.into(),
+    })?;
+    if up_to_date && mined_dataset_sha256(&data_dir).as_ref() == Some(&dataset_sha256) {
+        debug!("Dataset is unchanged, the ML processor is skipping processing.");
+        status.set_state(State::Idle);
+        return Ok(());
     }
 
     debug!("Processing dataset `{}`.", dataset_url);
-    let rules = process_data(dataset_url, &data_dir)?;
+    let dataset = parse_file(dataset_url, &file_path, columns, status)?;
+    let output = process_data(dataset)?;
 
-    let rules_path = rules_path(&data_dir);
+    status.set_state(State::Writing);
+    write_artifacts(dataset_url, &data_dir, columns, output, dataset_sha256).map_err(|source| {
+        Error::Write {
+            data_dir: data_dir.as_ref().into(),
+            source: source.into(),
+        }
+    })
+}
+
+/// Digest of the dataset the current model was mined from, if recorded.
+fn mined_dataset_sha256(data_dir: impl AsRef<Path>) -> Option<String> {
+    ModelMetadata::read(metadata_path(data_dir))
+        .ok()
+        .and_then(|metadata| metadata.dataset_sha256)
+}
+
+fn write_artifacts(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    columns: &Columns,
+    output: MiningOutput,
+    dataset_sha256: String,
+) -> Result<()> {
+    let MiningOutput {
+        rules,
+        n_transactions,
+        catalog,
+        frequencies,
+        itemsets,
+    } = output;
+
+    let catalog_path = catalog_path(&data_dir);
     debug!(
-        "Writing {} rules to `{}`.",
-        rules.len(),
-        rules_path.display()
+        "Writing {} songs to `{}`.",
+        catalog.len(),
+        catalog_path.display()
     );
-    write_rules(&rules, rules_path)?;
+    write_catalog(&catalog, catalog_path)?;
 
-    debug!("Writing new checkpoint to `{}`.", checkpoint_path.display());
-    write_checkpoint(dataset_url, &checkpoint_path)?;
+    let frequencies_path = frequencies_path(&data_dir);
+    debug!(
+        "Writing {} item frequencies to `{}`.",
+        frequencies.counts.len(),
+        frequencies_path.display()
+    );
+    write_frequencies(&frequencies, frequencies_path)?;
+
+    let itemsets_path = itemsets_path(&data_dir);
+    debug!(
+        "Writing {} frequent itemsets to `{}`.",
+        itemsets.counts.len(),
+        itemsets_path.display()
+    );
+    write_itemsets(&itemsets, itemsets_path)?;
+
+    debug!(
+        "Writing {} rules, model metadata, and checkpoint to `{}`.",
+        rules.len(),
+        data_dir.as_ref().display()
+    );
+    new_model(
+        dataset_url,
+        columns,
+        rules,
+        n_transactions,
+        Some(MINING_PARAMETERS),
+        Some(dataset_sha256),
+    )
+    .write(&data_dir)?;
     Ok(())
 }
 
-pub fn write_rules(rules: &[Rule], path: impl AsRef<Path>) -> Result<()> {
-    let file = File::create(&path).context("Failed to open rules file")?;
-    serialize_into(file, rules).context("Failed to write rules")?;
+/// Replace the rules in `data_dir` with rules read from `input`,
+/// e.g., hand-curated ones, and write a checkpoint so that
+/// the REST server reloads them.
+/// The catalog and item frequencies are left as they are.
+pub fn import(
+    input: impl AsRef<Path>,
+    format: RulesFormat,
+    columns: &Columns,
+    data_dir: impl AsRef<Path>,
+) -> Result<()> {
+    let input = input.as_ref();
+    let file =
+        File::open(input).with_context(|| format!("Failed to open `{}`", input.display()))?;
+    let rules = import_rules(BufReader::new(file), format)
+        .with_context(|| format!("Failed to import rules from `{}`", input.display()))?;
+
+    debug!(
+        "Writing {} imported rules to `{}`.",
+        rules.len(),
+        data_dir.as_ref().display()
+    );
+    // The itemsets of a mined model do not describe the imported rules.
+    let itemsets_path = itemsets_path(&data_dir);
+    match fs::remove_file(&itemsets_path) {
+        Err(why) if why.kind() != io::ErrorKind::NotFound => {
+            return Err(why)
+                .with_context(|| format!("Failed to remove `{}`", itemsets_path.display()));
+        }
+        _ => {}
+    }
+    // The checkpoint is whitespace-separated.
+    let source = format!("import:{}", input.display()).replace(char::is_whitespace, "%20");
+    new_model(&source, columns, rules, 0, None, None).write(&data_dir)?;
     Ok(())
 }
+
+/// Read rules from a bincode rules file,
+/// or from an exported JSON Lines or CSV file by its extension.
+pub fn load_rules(path: impl AsRef<Path>) -> Result<Vec<Rule>> {
+    let path = path.as_ref();
+    match RulesFormat::from_extension(path) {
+        Some(format @ (RulesFormat::JsonLines | RulesFormat::Csv)) => {
+            let file =
+                File::open(path).with_context(|| format!("Failed to open `{}`", path.display()))?;
+            import_rules(BufReader::new(file), format)
+        }
+        _ => Ok(read_rules(path)?),
+    }
+}
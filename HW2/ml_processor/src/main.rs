@@ -1,24 +1,410 @@
-use std::env;
+use std::{
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
 
-use anyhow::Result;
-use log::LevelFilter;
-use ml_processor::run;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use log::{error, LevelFilter};
+use ml_processor::*;
+use serde::Serialize;
+use shared::{checkpoint_path, itemsets_path, read_itemsets, read_rules, rules_path, MAX_LENGTH};
 
-fn main() -> Result<()> {
+const DEFAULT_DATASET_URL: &str =
+    "https://homepages.dcc.ufmg.br/~cunha/hosted/cloudcomp-2023s2-datasets/2023_spotify_ds1.csv";
+
+/// Mine song recommendation rules from a playlist dataset.
+/// Flags override the corresponding environment variables.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Defaults to `mine`.
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Arguments of `mine` when run without a subcommand.
+    #[command(flatten)]
+    mine: MineArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Mine rules from the dataset unless the checkpoint is up to date.
+    Mine(MineArgs),
+    /// Print statistics of the rules file.
+    Inspect(DataDirArgs),
+    /// Check if the checkpoint matches the inputs without mining;
+    /// exit with 1 if it does not.
+    Check(DatasetArgs),
+    /// Export the rules file as JSON Lines, CSV, or Parquet.
+    Export(ExportArgs),
+    /// Mine on a training split of the dataset and report
+    /// recommendation metrics on the held-out transactions.
+    Evaluate(EvaluateArgs),
+    /// Mine and evaluate with every combination of the given thresholds
+    /// on the same training split, and tabulate the results.
+    Sweep(SweepArgs),
+    /// Compare two rules files; exit with 1 if they differ too much.
+    Diff(DiffArgs),
+    /// Replace the rules file with rules from a JSON Lines or CSV file.
+    Import(ImportArgs),
+}
+
+#[derive(Debug, Args)]
+struct DataDirArgs {
+    /// Directory storing the dataset and generated artifacts.
+    #[arg(long, env = "DATA_DIR", default_value = "ml-data")]
+    data_dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct DatasetArgs {
+    #[command(flatten)]
+    data_dir: DataDirArgs,
+    #[arg(long, env = "DATASET_URL", default_value = DEFAULT_DATASET_URL)]
+    dataset_url: String,
+    #[command(flatten)]
+    columns: ColumnArgs,
+}
+
+impl DatasetArgs {
+    fn columns(&self) -> Result<Columns> {
+        self.columns.columns()
+    }
+}
+
+#[derive(Debug, Args)]
+struct ColumnArgs {
+    /// Column grouping items into transactions.
+    #[arg(long, env = "TRANSACTION_COLUMN", default_value = DEFAULT_TRANSACTION_COLUMN)]
+    transaction_column: String,
+    /// Comma-separated columns making up an item.
+    #[arg(long, env = "ITEM_COLUMNS", default_value = DEFAULT_ITEM_COLUMN)]
+    item_columns: String,
+}
+
+impl ColumnArgs {
+    fn columns(&self) -> Result<Columns> {
+        Columns::new(&self.transaction_column, &self.item_columns)
+    }
+}
+
+#[derive(Debug, Args)]
+struct MineArgs {
+    #[command(flatten)]
+    dataset: DatasetArgs,
+    /// Run as a daemon rerunning every this many seconds, at least 1.
+    #[arg(long, env = "DAEMON_INTERVAL_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    daemon_interval_secs: Option<u64>,
+    /// In daemon mode, read the dataset URL from this file instead,
+    /// rerunning as soon as it changes.
+    #[arg(long, env = "DATASET_URL_FILE", requires = "daemon_interval_secs")]
+    dataset_url_file: Option<PathBuf>,
+    /// In daemon mode, serve the status over HTTP on this port.
+    #[arg(long, env = "STATUS_PORT", requires = "daemon_interval_secs")]
+    status_port: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+struct ExportArgs {
+    #[command(flatten)]
+    data_dir: DataDirArgs,
+    /// Write to this file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// `jsonl`, `csv`, or `parquet`;
+    /// defaults to the output extension, or else `jsonl`.
+    #[arg(long, short)]
+    format: Option<RulesFormat>,
+}
+
+#[derive(Debug, Args)]
+struct EvaluationArgs {
+    /// Seed of the train/test split and of the hidden songs.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Fraction of transactions held out for testing.
+    #[arg(long, default_value_t = 0.2)]
+    test_fraction: f32,
+    /// Songs hidden from each test transaction.
+    #[arg(long, default_value_t = 1)]
+    hidden: usize,
+    /// Number of recommendations scored.
+    #[arg(long, default_value_t = MAX_LENGTH)]
+    k: usize,
+}
+
+impl EvaluationArgs {
+    fn config(&self) -> Result<EvaluationConfig> {
+        if !(0.0..1.0).contains(&self.test_fraction) {
+            bail!("The test fraction must be in [0, 1).");
+        }
+        if self.hidden == 0 || self.k == 0 {
+            bail!("The numbers of hidden songs and of recommendations must be positive.");
+        }
+        Ok(EvaluationConfig {
+            seed: self.seed,
+            test_fraction: self.test_fraction,
+            n_hidden: self.hidden,
+            k: self.k,
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+struct EvaluateArgs {
+    #[command(flatten)]
+    dataset: DatasetArgs,
+    #[command(flatten)]
+    evaluation: EvaluationArgs,
+    /// Write the JSON report to this file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct SweepArgs {
+    #[command(flatten)]
+    dataset: DatasetArgs,
+    #[command(flatten)]
+    evaluation: EvaluationArgs,
+    /// Comma-separated minimum supports to try.
+    #[arg(long, value_delimiter = ',', default_value = "0.01,0.025,0.05")]
+    min_supports: Vec<f32>,
+    /// Comma-separated minimum confidences to try.
+    #[arg(long, value_delimiter = ',', default_value = "0.5,0.7,0.9")]
+    min_confidences: Vec<f32>,
+    /// Comma-separated maximum itemset lengths to try.
+    #[arg(long, value_delimiter = ',', default_value = "2,4,8")]
+    max_lengths: Vec<usize>,
+    /// Also write the table as CSV to this file.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// Rules file, either bincode or exported JSON Lines or CSV.
+    old: PathBuf,
+    new: PathBuf,
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+    /// Maximum fraction of rules added or removed before failing.
+    #[arg(long)]
+    max_change: Option<f32>,
+}
+
+#[derive(Debug, Args)]
+struct ImportArgs {
+    #[command(flatten)]
+    data_dir: DataDirArgs,
+    /// File with one rule per line or row, in the `export` schema.
+    input: PathBuf,
+    /// `jsonl` or `csv`; defaults to the input extension.
+    #[arg(long, short)]
+    format: Option<RulesFormat>,
+    /// Columns the rules' items were mined from,
+    /// recorded so that `mine` re-mines if they differ.
+    #[command(flatten)]
+    columns: ColumnArgs,
+}
+
+/// Counting every allocation slows down the other commands,
+/// so `sweep` only measures memory when built with `peak-memory`.
+#[cfg(feature = "peak-memory")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() -> Result<ExitCode> {
     env_logger::builder()
         .filter_module("ml_processor", LevelFilter::Debug)
         .parse_default_env()
         .init();
 
-    let dataset_url = match env::var("DATASET_URL") {
-        Ok(d) => Box::leak(d.into()),
-        Err(_) => "https://homepages.dcc.ufmg.br/~cunha/hosted/cloudcomp-2023s2-datasets/2023_spotify_ds1.csv",
-    };
-    let data_dir = match env::var("DATA_DIR") {
-        Ok(d) => Box::leak(d.into()),
-        Err(_) => "ml-data",
-    };
-    run(dataset_url, data_dir)?;
+    let Cli {
+        command,
+        mine: mine_args,
+    } = Cli::parse();
+    let command = command.unwrap_or(Command::Mine(mine_args));
+    match command {
+        Command::Mine(args) => return mine(args),
+        Command::Inspect(DataDirArgs { data_dir }) => {
+            let rules = read_rules(rules_path(data_dir))?;
+            println!("{}", RuleStats::new(&rules));
+        }
+        Command::Check(args) => {
+            let columns = args.columns()?;
+            let checkpoint_path = checkpoint_path(&args.data_dir.data_dir);
+            match check_checkpoint(&args.dataset_url, &columns, checkpoint_path) {
+                Ok(true) => println!("Checkpoint is up to date."),
+                Ok(false) => {
+                    println!("Checkpoint is outdated.");
+                    return Ok(ExitCode::FAILURE);
+                }
+                Err(why) => {
+                    println!("Checkpoint is invalid: {why:#}.");
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        Command::Export(ExportArgs {
+            data_dir,
+            output,
+            format,
+        }) => {
+            let format = format
+                .or_else(|| output.as_ref().and_then(RulesFormat::from_extension))
+                .unwrap_or(RulesFormat::JsonLines);
+            let rules = read_rules(rules_path(&data_dir.data_dir))?;
+            let itemsets_path = itemsets_path(&data_dir.data_dir);
+            let itemsets = match itemsets_path.exists() {
+                true => Some(read_itemsets(itemsets_path)?),
+                false => None,
+            };
+            let writer: Box<dyn Write + Send> = match output {
+                Some(path) => Box::new(
+                    File::create(&path).with_context(|| format!("Failed to create {path:?}"))?,
+                ),
+                None if format == RulesFormat::Parquet => {
+                    bail!("Exporting to Parquet requires `--output`.")
+                }
+                None => Box::new(stdout()),
+            };
+            export_rules(&rules, itemsets.as_ref(), format, BufWriter::new(writer))?;
+        }
+        Command::Evaluate(EvaluateArgs {
+            dataset,
+            evaluation,
+            output,
+        }) => {
+            let config = evaluation.config()?;
+            let Dataset { transactions, .. } = ingest(
+                &dataset.dataset_url,
+                &dataset.data_dir.data_dir,
+                &dataset.columns()?,
+                &Status::default(),
+            )?;
+            let report = evaluate(&transactions, MINING_PARAMETERS, &config);
+            write_json_report(&report, output)?;
+        }
+        Command::Sweep(SweepArgs {
+            dataset,
+            evaluation,
+            min_supports,
+            min_confidences,
+            max_lengths,
+            output,
+        }) => {
+            let config = evaluation.config()?;
+            let grid = SweepGrid {
+                min_supports,
+                min_confidences,
+                max_lengths,
+            };
+            let Dataset { transactions, .. } = ingest(
+                &dataset.dataset_url,
+                &dataset.data_dir.data_dir,
+                &dataset.columns()?,
+                &Status::default(),
+            )?;
+            let results = sweep(&transactions, &grid, &config);
+            println!("{}", SweepTable(&results));
+            if let Some(path) = output {
+                let file =
+                    File::create(&path).with_context(|| format!("Failed to create {path:?}"))?;
+                write_sweep_csv(&results, BufWriter::new(file))?;
+            }
+        }
+        Command::Diff(DiffArgs {
+            old,
+            new,
+            json,
+            max_change,
+        }) => {
+            let diff = ModelDiff::new(&load_rules(old)?, &load_rules(new)?);
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&diff)?),
+                false => println!("{diff}"),
+            }
+            if let Some(max_change) = max_change {
+                if diff.change_ratio > max_change {
+                    eprintln!(
+                        "Change ratio {:.4} exceeds the maximum {max_change}.",
+                        diff.change_ratio
+                    );
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        Command::Import(ImportArgs {
+            data_dir,
+            input,
+            format,
+            columns,
+        }) => {
+            let format = match format {
+                Some(format) => format,
+                None => RulesFormat::from_extension(&input).with_context(|| {
+                    format!("Cannot infer the format of {input:?}, pass `--format`")
+                })?,
+            };
+            import(&input, format, &columns.columns()?, data_dir.data_dir)?;
+        }
+    }
 
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_json_report(report: &impl Serialize, output: Option<PathBuf>) -> Result<()> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => {
+            Box::new(File::create(&path).with_context(|| format!("Failed to create {path:?}"))?)
+        }
+        None => Box::new(stdout()),
+    };
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(writer)?;
     Ok(())
 }
+
+/// Exit with the error's code if mining once fails,
+/// so that supervisors can retry only retryable failures.
+fn mine(args: MineArgs) -> Result<ExitCode> {
+    let MineArgs {
+        dataset,
+        daemon_interval_secs,
+        dataset_url_file,
+        status_port,
+    } = args;
+    let columns = dataset.columns()?;
+    let data_dir = dataset.data_dir.data_dir;
+
+    let Some(interval) = daemon_interval_secs else {
+        return Ok(match run(&dataset.dataset_url, data_dir, &columns) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(why) => {
+                error!("{} (retryable: {}).", why.chain(), why.is_retryable());
+                ExitCode::from(why.exit_code())
+            }
+        });
+    };
+    let url_source = match dataset_url_file {
+        Some(path) => UrlSource::File(path),
+        None => UrlSource::Fixed(dataset.dataset_url),
+    };
+    let status = Status::default();
+    if let Some(port) = status_port {
+        spawn_status_server(port, status.clone())?;
+    }
+    Daemon {
+        url_source,
+        data_dir,
+        columns,
+        interval: Duration::from_secs(interval),
+        status,
+    }
+    .run()
+}
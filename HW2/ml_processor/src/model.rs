@@ -0,0 +1,35 @@
+use super::*;
+
+/// Bundle `rules` with metadata and a fresh checkpoint
+/// describing how they were made.
+pub fn new_model(
+    dataset_url: &str,
+    columns: &Columns,
+    rules: Vec<Rule>,
+    n_transactions: usize,
+    parameters: Option<MiningParameters>,
+    dataset_sha256: Option<String>,
+) -> Model {
+    let metadata = ModelMetadata {
+        ml_processor_version: crate_version!().into(),
+        dataset_url: dataset_url.into(),
+        transaction_column: columns.transaction.clone(),
+        item_columns: columns.items.clone(),
+        item_separator: ITEM_SEPARATOR.into(),
+        n_transactions,
+        n_rules: rules.len(),
+        parameters,
+        dataset_sha256,
+    };
+    let checkpoint = Checkpoint::new(
+        crate_version!(),
+        dataset_url,
+        &columns.transaction,
+        &columns.items,
+    );
+    Model {
+        rules,
+        metadata: Some(metadata),
+        checkpoint,
+    }
+}
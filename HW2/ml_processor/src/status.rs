@@ -0,0 +1,121 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use serde::Serialize;
+
+use super::*;
+
+/// What the ML processor is doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    #[default]
+    Idle,
+    Downloading,
+    Mining,
+    Writing,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StatusReport {
+    pub state: State,
+    pub dataset_url: Option<String>,
+    /// Seconds since UNIX epoch when the rules were last confirmed up to date.
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Shared, cheaply cloneable status of the ML processor.
+#[derive(Clone, Debug, Default)]
+pub struct Status(Arc<Mutex<StatusReport>>);
+
+impl Status {
+    pub fn set_state(&self, state: State) {
+        debug!("State: {state:?}.");
+        self.update(|report| report.state = state);
+    }
+
+    pub fn start_run(&self, dataset_url: &str) {
+        self.update(|report| report.dataset_url = Some(dataset_url.into()));
+    }
+
+    pub fn finish_run(&self, result: &Result<(), Error>) {
+        self.update(|report| {
+            report.state = State::Idle;
+            match result {
+                Ok(()) => {
+                    report.last_success = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|duration| duration.as_secs());
+                    report.last_error = None;
+                }
+                Err(why) => report.last_error = Some(why.chain()),
+            }
+        });
+    }
+
+    pub fn report(&self) -> StatusReport {
+        self.0.lock().expect("Status lock poisoned").clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut StatusReport)) {
+        f(&mut self.0.lock().expect("Status lock poisoned"))
+    }
+}
+
+/// How long a status client may take to send its request or read the response.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer every HTTP request on `port` with the status as JSON,
+/// in a background thread, returning the bound address.
+pub fn spawn_status_server(port: u16, status: Status) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .with_context(|| format!("Failed to bind status server to port {port}"))?;
+    let address = listener.local_addr()?;
+    debug!("Serving status on {address}.");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(why) => {
+                    error!("Failed to accept a status connection: {why:?}");
+                    continue;
+                }
+            };
+            // One thread per connection, so that a slow client delays no other.
+            let status = status.clone();
+            thread::spawn(move || {
+                if let Err(why) = respond_status(stream, &status) {
+                    error!("Failed to serve status: {why:?}");
+                }
+            });
+        }
+    });
+    Ok(address)
+}
+
+fn respond_status(mut stream: TcpStream, status: &Status) -> Result<()> {
+    stream.set_read_timeout(Some(STATUS_TIMEOUT))?;
+    stream.set_write_timeout(Some(STATUS_TIMEOUT))?;
+    // Drain the request head; the path does not matter.
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let body = serde_json::to_string(&status.report())?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{fmt, io::Write, time::Instant};
+
+use serde::Serialize;
+
+use super::*;
+
+/// Values to try for each mining parameter; every combination is mined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepGrid {
+    pub min_supports: Vec<f32>,
+    pub min_confidences: Vec<f32>,
+    pub max_lengths: Vec<usize>,
+}
+
+impl SweepGrid {
+    pub fn settings(&self) -> impl Iterator<Item = MiningParameters> + '_ {
+        self.min_supports.iter().flat_map(move |&min_support| {
+            self.min_confidences
+                .iter()
+                .flat_map(move |&min_confidence| {
+                    self.max_lengths
+                        .iter()
+                        .map(move |&max_length| MiningParameters {
+                            min_support,
+                            min_confidence,
+                            max_length,
+                        })
+                })
+        })
+    }
+}
+
+/// Outcome of mining the training split with one setting.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SweepResult {
+    pub parameters: MiningParameters,
+    pub n_rules: usize,
+    pub mining_secs: f64,
+    /// Peak heap bytes allocated while mining, beyond what was allocated before,
+    /// if the counting allocator is installed.
+    pub peak_memory_bytes: Option<usize>,
+    pub metrics: Metrics,
+}
+
+/// Mine the same training split of `transactions` with every setting
+/// in `grid`, and evaluate each on the same test split.
+pub fn sweep(
+    transactions: &[Transaction],
+    grid: &SweepGrid,
+    config: &EvaluationConfig,
+) -> Vec<SweepResult> {
+    let Split { train, test } = split_transactions(transactions, config);
+    grid.settings()
+        .map(|parameters| {
+            debug!("Sweeping {parameters:?}.");
+            let baseline = reset_peak_memory();
+            let start = Instant::now();
+            let mined = mine(&train, parameters);
+            let mining_secs = start.elapsed().as_secs_f64();
+            let peak_memory_bytes = peak_memory().map(|peak| peak.saturating_sub(baseline));
+            let (metrics, _) = evaluate_rules(&mined, &test, config);
+            SweepResult {
+                parameters,
+                n_rules: mined.rules.len(),
+                mining_secs,
+                peak_memory_bytes,
+                metrics,
+            }
+        })
+        .collect()
+}
+
+const SWEEP_HEADER: [&str; 11] = [
+    "min_support",
+    "min_confidence",
+    "max_length",
+    "n_rules",
+    "mining_secs",
+    "peak_memory_bytes",
+    "precision_at_k",
+    "recall_at_k",
+    "hit_rate",
+    "coverage",
+    "mrr",
+];
+
+impl SweepResult {
+    fn row(&self) -> [String; 11] {
+        let MiningParameters {
+            min_support,
+            min_confidence,
+            max_length,
+        } = self.parameters;
+        let Metrics {
+            precision_at_k,
+            recall_at_k,
+            hit_rate,
+            coverage,
+            mrr,
+        } = self.metrics;
+        [
+            min_support.to_string(),
+            min_confidence.to_string(),
+            max_length.to_string(),
+            self.n_rules.to_string(),
+            format!("{:.3}", self.mining_secs),
+            self.peak_memory_bytes
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_default(),
+            format!("{precision_at_k:.4}"),
+            format!("{recall_at_k:.4}"),
+            format!("{hit_rate:.4}"),
+            format!("{coverage:.4}"),
+            format!("{mrr:.4}"),
+        ]
+    }
+}
+
+pub fn write_sweep_csv(results: &[SweepResult], writer: impl Write) -> Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    csv_writer.write_record(SWEEP_HEADER)?;
+    for result in results {
+        csv_writer.write_record(result.row())?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// The sweep results as an aligned text table.
+pub struct SweepTable<'a>(pub &'a [SweepResult]);
+
+impl fmt::Display for SweepTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<[String; 11]> = self.0.iter().map(SweepResult::row).collect();
+        let widths: Vec<usize> = SWEEP_HEADER
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                rows.iter()
+                    .map(|row| row[index].len())
+                    .chain([name.len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let header = SWEEP_HEADER.map(String::from);
+        for (index, row) in [&header].into_iter().chain(&rows).enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            for (column, (cell, width)) in row.iter().zip(&widths).enumerate() {
+                let separator = if column > 0 { "  " } else { "" };
+                write!(f, "{separator}{cell:>width$}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1 +1,546 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env::temp_dir,
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use flate2::{write::GzEncoder, Compression as GzLevel};
+
+use super::*;
+use compression::{read_decompressed, Compression};
+use itemsets::count_itemsets;
+
+const CSV: &str = "pid,track_name\n0,DNA.\n0,HUMBLE.\n1,DNA.\n";
+
+fn test_path(name: &str) -> PathBuf {
+    let dir = temp_dir().join(format!("ml_processor-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn read_to_string(path: &Path) -> String {
+    read_decompressed(path, |reader| {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Ok(content)
+    })
+    .unwrap()
+}
+
+#[test]
+fn compression_from_extension_and_magic_bytes() {
+    assert_eq!(
+        Compression::from_extension("ds1.csv.gz"),
+        Some(Compression::Gzip)
+    );
+    assert_eq!(
+        Compression::from_extension("ds1.csv.ZST"),
+        Some(Compression::Zstd)
+    );
+    assert_eq!(
+        Compression::from_extension("ds1.csv"),
+        Some(Compression::None)
+    );
+    assert_eq!(Compression::from_extension("ds1"), None);
+
+    assert_eq!(
+        Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd]),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::from_magic_bytes(b"pid,"), Compression::None);
+    assert_eq!(Compression::from_magic_bytes(&[]), Compression::None);
+}
+
+#[test]
+fn read_gzip_without_extension() {
+    let path = test_path("gzip-dataset");
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), GzLevel::default());
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+
+    assert_eq!(read_to_string(&path), CSV);
+}
+
+#[test]
+fn parse_decompressed_crlf_dataset() {
+    let path = test_path("crlf-dataset.csv.gz");
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), GzLevel::default());
+    encoder
+        .write_all(CSV.replace('\n', "\r\n").as_bytes())
+        .unwrap();
+    encoder.finish().unwrap();
+
+    let columns = Columns::new("pid", "track_name").unwrap();
+    let Dataset { transactions, .. } =
+        read_decompressed(&path, |reader| parse_dataset(reader, &columns)).unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert!(transactions[0].contains("HUMBLE."));
+    assert_eq!(transactions[1].len(), 1);
+}
+
+#[test]
+fn read_zstd() {
+    let path = test_path("dataset.csv.zst");
+    fs::write(&path, zstd::encode_all(CSV.as_bytes(), 0).unwrap()).unwrap();
+
+    assert_eq!(read_to_string(&path), CSV);
+}
+
+#[test]
+fn read_zip_picks_csv_member() {
+    let path = test_path("dataset.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    let options = zip::write::FileOptions::default();
+    writer.start_file("README.txt", options).unwrap();
+    writer.write_all(b"Not the dataset.").unwrap();
+    writer.start_file("ds1/dataset.csv", options).unwrap();
+    writer.write_all(CSV.as_bytes()).unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(read_to_string(&path), CSV);
+}
+
+#[test]
+fn composite_item_columns() {
+    let columns = Columns::new("pid", "artist_name, track_name").unwrap();
+    let indices = columns
+        .indices_in_header("track_name,pid,artist_name")
+        .unwrap();
+    let line = indices.parse_line("DNA.,7,Kendrick Lamar").unwrap();
+    assert_eq!(line.transaction, "7");
+    assert_eq!(line.item, "Kendrick Lamar – DNA.");
+    assert_eq!(line.name, Some("DNA."));
+    assert_eq!(line.artist, Some("Kendrick Lamar"));
+
+    assert!(indices.parse_line("DNA.,7").is_err());
+    assert!(columns.indices_in_header("pid,track_name").is_err());
+    assert!(Columns::new("pid", " , ").is_err());
+}
+
+#[test]
+fn export_import_round_trip() {
+    let rules = vec![Rule {
+        antecedent: ["DNA.".into(), "HUMBLE.".into()].into(),
+        consequent: ["Money Trees, Remix".into()].into(),
+        confidence: 0.75,
+        lift: 1.5,
+    }];
+    for format in [RulesFormat::JsonLines, RulesFormat::Csv] {
+        let mut exported = Vec::new();
+        export_rules(&rules, None, format, &mut exported).unwrap();
+        let imported = import_rules(exported.as_slice(), format).unwrap();
+        assert_eq!(imported.len(), 1, "{format}");
+        assert_eq!(imported[0].antecedent, rules[0].antecedent, "{format}");
+        assert_eq!(imported[0].consequent, rules[0].consequent, "{format}");
+        assert_eq!(imported[0].confidence, 0.75, "{format}");
+    }
+
+    let mut parquet = Vec::new();
+    export_rules(&rules, None, RulesFormat::Parquet, &mut parquet).unwrap();
+    assert!(parquet.starts_with(b"PAR1"));
+}
+
+#[test]
+fn import_hand_written_csv() {
+    let csv = "antecedent,consequent,support,confidence,lift\nDNA.,HUMBLE.,,0.9,2\n";
+    let rules = import_rules(csv.as_bytes(), RulesFormat::Csv).unwrap();
+    assert_eq!(rules[0].antecedent, ["DNA.".to_owned()].into());
+    assert_eq!(rules[0].consequent, ["HUMBLE.".to_owned()].into());
+    assert_eq!(rules[0].lift, 2.0);
+}
+
+#[test]
+fn count_rule_itemsets() {
+    let transactions: Vec<HashSet<&str>> = vec![
+        ["a", "b", "c"].into(),
+        ["a", "b"].into(),
+        ["a", "c"].into(),
+        ["d"].into(),
+    ];
+    let item_counts = [("a", 3), ("b", 2), ("c", 2), ("d", 1)].into();
+    let rules = vec![Rule {
+        antecedent: ["b".into()].into(),
+        consequent: ["a".into()].into(),
+        confidence: 1.0,
+        lift: 4.0 / 3.0,
+    }];
+    let parameters = MiningParameters {
+        min_support: 0.5,
+        min_confidence: 0.7,
+        max_length: 2,
+    };
+    let itemsets = count_itemsets(
+        transactions.iter(),
+        &item_counts,
+        &HashMap::new(),
+        &rules,
+        parameters,
+    );
+    assert_eq!(itemsets.n_transactions, 4);
+    assert_eq!(
+        itemsets.counts,
+        [
+            (vec!["a".to_owned()], 3),
+            (vec!["a".to_owned(), "b".to_owned()], 2),
+            (vec!["b".to_owned()], 2),
+            (vec!["c".to_owned()], 2),
+        ]
+    );
+
+    let record = RuleRecord::from(&rules[0]).with_support(&itemsets.count_map(), 4);
+    assert_eq!(record.support, Some(0.5));
+}
+
+#[test]
+fn keep_frequent_itemsets_without_rules() {
+    let transactions: Vec<HashSet<&str>> = vec![
+        ["a", "b", "c"].into(),
+        ["a", "b"].into(),
+        ["a", "c"].into(),
+        ["c", "d"].into(),
+    ];
+    let item_counts = [("a", 3), ("b", 2), ("c", 3), ("d", 1)].into();
+    // {a, c} is frequent, but neither a → c nor c → a reaches 0.7 confidence.
+    let frequent = [
+        (
+            1,
+            [(["a"].into(), 3), (["b"].into(), 2), (["c"].into(), 3)].into(),
+        ),
+        (2, [(["a", "b"].into(), 2), (["a", "c"].into(), 2)].into()),
+    ]
+    .into();
+    let rules = vec![Rule {
+        antecedent: ["b".into()].into(),
+        consequent: ["a".into()].into(),
+        confidence: 1.0,
+        lift: 4.0 / 3.0,
+    }];
+    let parameters = MiningParameters {
+        min_support: 0.5,
+        min_confidence: 0.7,
+        max_length: 2,
+    };
+    let itemsets = count_itemsets(
+        transactions.iter(),
+        &item_counts,
+        &frequent,
+        &rules,
+        parameters,
+    );
+    assert_eq!(
+        itemsets.counts,
+        [
+            (vec!["a".to_owned()], 3),
+            (vec!["c".to_owned()], 3),
+            (vec!["a".to_owned(), "b".to_owned()], 2),
+            (vec!["a".to_owned(), "c".to_owned()], 2),
+            (vec!["b".to_owned()], 2),
+        ]
+    );
+}
+
+#[test]
+fn diff_rules() {
+    let rule = |antecedent: &str, consequent: &str, confidence| Rule {
+        antecedent: [antecedent.into()].into(),
+        consequent: [consequent.into()].into(),
+        confidence,
+        lift: 1.0,
+    };
+    let old = [
+        rule("a", "b", 0.8),
+        rule("b", "a", 0.9),
+        rule("c", "d", 0.7),
+    ];
+    let new = [
+        rule("a", "b", 0.8),
+        rule("b", "a", 0.75),
+        rule("c", "e", 0.7),
+    ];
+    let diff = ModelDiff::new(&old, &new);
+
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].consequent, ["e"]);
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].consequent, ["d"]);
+    assert_eq!(diff.confidence_shifts.len(), 1);
+    assert_eq!(diff.confidence_shifts[0].old, 0.9);
+    assert_eq!(diff.gained_songs, ["e"]);
+    assert_eq!(diff.lost_songs, ["d"]);
+    assert_eq!(diff.change_ratio, 0.5);
+}
+
+#[test]
+fn evaluation_is_reproducible() {
+    let mut csv = String::from("pid,track_uri\n");
+    for pid in 0..40 {
+        for track in ["a", "b", "c"] {
+            csv.push_str(&format!("{pid},{track}\n"));
+        }
+        csv.push_str(&format!("{pid},{}\n", pid % 5));
+    }
+    let Dataset { transactions, .. } = parse_dataset(csv.as_bytes(), &Columns::default()).unwrap();
+    assert_eq!(transactions.len(), 40);
+
+    let config = EvaluationConfig {
+        seed: 7,
+        test_fraction: 0.25,
+        n_hidden: 1,
+        k: 4,
+    };
+    let report = evaluate(&transactions, MINING_PARAMETERS, &config);
+    assert_eq!(report.n_train, 30);
+    assert_eq!(report.n_test, 10);
+    assert_eq!(report.n_skipped, 0);
+    assert_eq!(report, evaluate(&transactions, MINING_PARAMETERS, &config));
+
+    let Split { train, test } = split_transactions(&transactions, &config);
+    let mined = MinedRules {
+        rules: Vec::new(),
+        frequencies: ItemFrequencies {
+            n_transactions: train.len(),
+            counts: vec![("a".into(), 30), ("b".into(), 30), ("c".into(), 30)],
+        },
+        itemsets: FrequentItemsets {
+            n_transactions: train.len(),
+            parameters: MINING_PARAMETERS,
+            counts: Vec::new(),
+        },
+    };
+    // Popularity alone recommends a, b, c, so any hidden one of them is a hit.
+    let (metrics, _) = evaluate_rules(&mined, &test, &config);
+    assert!(metrics.hit_rate > 0.0);
+    assert_eq!(metrics.coverage, 1.0);
+}
+
+#[test]
+fn sweep_grid_settings() {
+    let grid = SweepGrid {
+        min_supports: vec![0.01, 0.05],
+        min_confidences: vec![0.7],
+        max_lengths: vec![2, 8],
+    };
+    let settings: Vec<MiningParameters> = grid.settings().collect();
+    assert_eq!(settings.len(), 4);
+    assert_eq!(
+        settings[1],
+        MiningParameters {
+            min_support: 0.01,
+            min_confidence: 0.7,
+            max_length: 8,
+        }
+    );
+
+    let Dataset { transactions, .. } =
+        parse_dataset(CSV.as_bytes(), &Columns::new("pid", "track_name").unwrap()).unwrap();
+    let results = sweep(&transactions, &grid, &EvaluationConfig::default());
+    let mut csv = Vec::new();
+    write_sweep_csv(&results, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 5);
+    assert!(csv.starts_with("min_support,min_confidence,max_length,n_rules,"));
+}
+
+#[test]
+fn error_chain_and_exit_codes() {
+    let source = parse_dataset("pid,track_name\n".as_bytes(), &Columns::default())
+        .err()
+        .unwrap();
+    let error = Error::Parse {
+        url: "ds1.csv".into(),
+        source: source.into(),
+    };
+    assert!(!error.is_retryable());
+    assert_eq!(error.exit_code(), 65);
+    assert!(error
+        .chain()
+        .starts_with("Failed to parse dataset `ds1.csv`: "));
+
+    let error = Error::Download {
+        url: "ds1.csv".into(),
+        source: "aria2c failed".into(),
+    };
+    assert!(error.is_retryable());
+    assert_eq!(error.exit_code(), 75);
+}
+
+#[test]
+fn daemon_rechecks_same_url_after_interval() {
+    let url_file = test_path("dataset-url");
+    fs::write(&url_file, "https://example.com/ds1.csv\n").unwrap();
+    let daemon = Daemon {
+        url_source: UrlSource::File(url_file.clone()),
+        data_dir: test_path("daemon"),
+        columns: Columns::default(),
+        interval: Duration::from_secs(60),
+        status: Status::default(),
+    };
+    let mut schedule = Schedule::default();
+    let start = Instant::now();
+    let mut runs = Vec::new();
+    let mut tick = |schedule: &mut Schedule, at| {
+        daemon.tick(schedule, start + at, |url, recheck| {
+            runs.push((url.to_owned(), recheck));
+            Ok(())
+        })
+    };
+
+    tick(&mut schedule, Duration::ZERO);
+    tick(&mut schedule, Duration::from_secs(1));
+    tick(&mut schedule, Duration::from_secs(61));
+    fs::write(&url_file, "https://example.com/ds2.csv").unwrap();
+    tick(&mut schedule, Duration::from_secs(62));
+    assert_eq!(
+        runs,
+        [
+            ("https://example.com/ds1.csv".into(), false),
+            ("https://example.com/ds1.csv".into(), true),
+            ("https://example.com/ds2.csv".into(), false),
+        ]
+    );
+    assert_eq!(
+        daemon.status.report().dataset_url.as_deref(),
+        Some("https://example.com/ds2.csv")
+    );
+}
+
+#[test]
+fn daemon_reports_failed_runs() {
+    let daemon = Daemon {
+        url_source: UrlSource::Fixed("https://example.com/ds1.csv".into()),
+        data_dir: test_path("daemon-failing"),
+        columns: Columns::default(),
+        interval: Duration::from_secs(60),
+        status: Status::default(),
+    };
+    daemon.tick(&mut Schedule::default(), Instant::now(), |url, _| {
+        Err(Error::Download {
+            url: url.into(),
+            source: "aria2c failed".into(),
+        })
+    });
+    let report = daemon.status.report();
+    assert_eq!(report.state, State::Idle);
+    assert!(report.last_error.unwrap().contains("aria2c failed"));
+    assert_eq!(report.last_success, None);
+}
+
+#[test]
+fn status_server_responds_with_json() {
+    let status = Status::default();
+    status.start_run("https://example.com/ds1.csv");
+    let address = spawn_status_server(0, status).unwrap();
+    // A client that sends nothing must not hold up the others.
+    let _silent = std::net::TcpStream::connect(("127.0.0.1", address.port())).unwrap();
+
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", address.port())).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let report: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(report["state"], "idle");
+    assert_eq!(report["dataset_url"], "https://example.com/ds1.csv");
+}
+
+#[test]
+fn import_rejects_empty_itemsets_and_records_columns() {
+    let csv = "antecedent,consequent,support,confidence,lift\n,HUMBLE.,,0.9,2\n";
+    let error = import_rules(csv.as_bytes(), RulesFormat::Csv).unwrap_err();
+    assert!(format!("{error:#}").contains("Empty antecedent"));
+    let jsonl = r#"{"antecedent":["DNA."],"consequent":[],"confidence":0.9,"lift":2}"#;
+    let error = import_rules(jsonl.as_bytes(), RulesFormat::JsonLines).unwrap_err();
+    assert!(format!("{error:#}").contains("Empty consequent"));
+
+    let input = test_path("imported.csv");
+    fs::write(
+        &input,
+        "antecedent,consequent,support,confidence,lift\nDNA.,HUMBLE.,,0.9,2\n",
+    )
+    .unwrap();
+    let data_dir = test_path("import");
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(itemsets_path(&data_dir), "{}").unwrap();
+    let columns = Columns::new("pid", "track_name").unwrap();
+    import(&input, RulesFormat::Csv, &columns, &data_dir).unwrap();
+    assert!(!itemsets_path(&data_dir).exists());
+    let temp_files = fs::read_dir(&data_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("tmp".as_ref()))
+        .count();
+    assert_eq!(temp_files, 0);
+    let checkpoint = Checkpoint::read(checkpoint_path(&data_dir)).unwrap();
+    assert_eq!(checkpoint.transaction_column, "pid");
+    assert_eq!(checkpoint.item_columns, ["track_name"]);
+    let metadata = ModelMetadata::read(metadata_path(&data_dir)).unwrap();
+    assert_eq!(metadata.item_columns, ["track_name"]);
+}
+
+#[test]
+fn local_read_errors_are_retryable() {
+    let status = Status::default();
+    let columns = Columns::new("pid", "track_name").unwrap();
+    let error = parse_file("ds1.csv", test_path("missing.csv"), &columns, &status)
+        .err()
+        .unwrap();
+    assert!(matches!(error, Error::Read { .. }), "{error:?}");
+    assert!(error.is_retryable());
+    assert_eq!(error.exit_code(), 74);
+
+    let corrupt = test_path("corrupt.csv.gz");
+    fs::write(&corrupt, b"\x1f\x8b\x08\0not gzip").unwrap();
+    let error = parse_file("ds1.csv", &corrupt, &columns, &status)
+        .err()
+        .unwrap();
+    assert!(matches!(error, Error::Parse { .. }), "{error:?}");
+}
+
+#[test]
+fn dataset_file_name_is_the_last_path_segment() {
+    assert_eq!(
+        dataset_file_name("https://example.com/data/ds1.csv?token=1#top").unwrap(),
+        "ds1.csv"
+    );
+    assert_eq!(
+        dataset_file_name("ftp://example.com/ds1.csv.gz").unwrap(),
+        "ds1.csv.gz"
+    );
+    for url in [
+        "https://example.com",
+        "https://example.com/",
+        "https://example.com/..",
+        "https://example.com/a/.",
+        "https://example.com/rules.bincode",
+        "https://example.com/ml_processor_checkpoint.txt",
+    ] {
+        assert!(dataset_file_name(url).is_err(), "{url}");
+    }
+
+    let status = Status::default();
+    let error = fetch("https://example.com/", test_path("url"), false, &status)
+        .err()
+        .unwrap();
+    assert!(matches!(error, Error::Url { .. }), "{error:?}");
+    assert!(!error.is_retryable());
+    assert_eq!(error.exit_code(), 64);
+}
+
+#[test]
+fn check_http_url_requires_http_and_a_host() {
+    check_http_url("https://example.com/ds1.csv").unwrap();
+    check_http_url("HTTP://example.com/ds1.csv").unwrap();
+    for url in [
+        "ds1.csv",
+        "file:///etc/passwd",
+        "ftp://example.com/ds1.csv",
+        "https:///ds1.csv",
+    ] {
+        assert!(check_http_url(url).is_err(), "{url}");
+    }
+}
@@ -1,90 +1,359 @@
 use std::{
+    any::Any,
     collections::{HashMap, HashSet},
+    io::{self, BufRead},
+    panic::{catch_unwind, AssertUnwindSafe},
     path::PathBuf,
     process::Command,
+    sync::Arc,
 };
 
 use apriori::{apriori, Rule};
+use sha2::{Digest, Sha256};
+
+use columns::ParsedLine;
+use compression::read_decompressed;
+use itemsets::count_itemsets;
 
 use super::*;
 
-const MIN_SUPPORT: f32 = 0.025;
-const MIN_CONFIDENCE: f32 = 0.7;
-
-pub fn process_data(dataset_url: &str, data_dir: impl AsRef<Path>) -> Result<Vec<Rule>> {
-    let dataset_file_content = read_url_file(dataset_url, data_dir)?;
-    let mut data_set_lines = dataset_file_content.lines();
-
-    let (playlist_id_index, track_name_index) = get_playlist_id_and_track_name_index_in_header(
-        data_set_lines
-            .next()
-            .context("The dataset file is empty.")?,
-    )?;
-
-    let mut raw_transactions = HashMap::<&str, HashSet<&str>>::new();
-    for line in data_set_lines {
-        let mut playlist_id = None;
-        let mut track_name = None;
-        for (index, attribute) in line.split(',').enumerate() {
-            if index == playlist_id_index {
-                playlist_id = Some(attribute);
-            } else if index == track_name_index {
-                track_name = Some(attribute);
+pub const MINING_PARAMETERS: MiningParameters = MiningParameters {
+    min_support: 0.025,
+    min_confidence: 0.7,
+    max_length: MAX_LENGTH,
+};
+
+pub struct MiningOutput {
+    pub rules: Vec<Rule>,
+    pub n_transactions: usize,
+    pub catalog: SongCatalog,
+    pub frequencies: ItemFrequencies,
+    pub itemsets: FrequentItemsets,
+}
+
+/// The items of one transaction, interned.
+pub type Transaction = HashSet<Arc<str>>;
+
+/// A parsed dataset, with transactions sorted by their ID
+/// so that it is processed deterministically.
+pub struct Dataset {
+    pub transactions: Vec<Transaction>,
+    pub catalog: SongCatalog,
+}
+
+pub struct MinedRules {
+    pub rules: Vec<Rule>,
+    pub frequencies: ItemFrequencies,
+    pub itemsets: FrequentItemsets,
+}
+
+pub fn process_data(dataset: Dataset) -> Result<MiningOutput, Error> {
+    let Dataset {
+        transactions,
+        mut catalog,
+    } = dataset;
+    // Apriori reports no errors but may panic, e.g., on an overflowing
+    // capacity; allocation failure aborts the process instead.
+    let MinedRules {
+        rules,
+        frequencies,
+        itemsets,
+    } = catch_unwind(AssertUnwindSafe(|| mine(&transactions, MINING_PARAMETERS)))
+        .map_err(|panic| Error::Mine(panic_message(panic).into()))?;
+
+    let item_counts: HashMap<&str, usize> = frequencies
+        .counts
+        .iter()
+        .map(|(item, count)| (item.as_str(), *count))
+        .collect();
+    for (item, song) in &mut catalog {
+        song.count = item_counts.get(item.as_str()).copied().unwrap_or_default();
+    }
+    debug!("Got {} songs in the catalog.", catalog.len());
+
+    Ok(MiningOutput {
+        rules,
+        n_transactions: transactions.len(),
+        catalog,
+        frequencies,
+        itemsets,
+    })
+}
+
+/// Download and parse the dataset.
+pub fn ingest(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    columns: &Columns,
+    status: &Status,
+) -> Result<Dataset, Error> {
+    let file_path = fetch(dataset_url, data_dir, false, status)?;
+    parse_file(dataset_url, file_path, columns, status)
+}
+
+/// Download the dataset into `data_dir`, resuming any earlier download of it,
+/// or, if `recheck`, replacing the earlier download only if the server
+/// reports the dataset modified since.
+pub fn fetch(
+    dataset_url: &str,
+    data_dir: impl AsRef<Path>,
+    recheck: bool,
+    status: &Status,
+) -> Result<PathBuf, Error> {
+    let file_name = dataset_file_name(dataset_url).map_err(|source| Error::Url {
+        url: dataset_url.into(),
+        source: source.into(),
+    })?;
+    status.set_state(State::Downloading);
+    download(dataset_url, file_name, data_dir, recheck).map_err(|source| Error::Download {
+        url: dataset_url.into(),
+        source: source.into(),
+    })
+}
+
+/// Parse the dataset downloaded from `dataset_url` to `file_path`.
+pub fn parse_file(
+    dataset_url: &str,
+    file_path: impl AsRef<Path>,
+    columns: &Columns,
+    status: &Status,
+) -> Result<Dataset, Error> {
+    status.set_state(State::Mining);
+    let file_path = file_path.as_ref();
+    read_decompressed(file_path, |reader| parse_dataset(reader, columns)).map_err(|source| {
+        match is_local_io_error(&source) {
+            true => Error::Read {
+                path: file_path.into(),
+                source: source.into(),
+            },
+            false => Error::Parse {
+                url: dataset_url.into(),
+                source: source.into(),
+            },
+        }
+    })
+}
+
+/// Whether reading failed locally rather than on malformed data,
+/// which decompressors and UTF-8 decoding report as I/O errors too.
+fn is_local_io_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|error| error.downcast_ref::<io::Error>())
+        .any(|error| {
+            !matches!(
+                error.kind(),
+                io::ErrorKind::InvalidData
+                    | io::ErrorKind::InvalidInput
+                    | io::ErrorKind::UnexpectedEof
+            )
+        })
+}
+
+/// Hex SHA-256 of the file at `path`.
+pub fn file_sha256(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("Failed to open `{path:?}`"))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read `{path:?}`"))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parse the dataset line by line from `reader`.
+pub fn parse_dataset(mut reader: impl BufRead, columns: &Columns) -> Result<Dataset> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("The dataset file is empty.");
+    }
+    let column_indices = columns.indices_in_header(trim_line_end(&line))?;
+
+    let mut interned_items = HashSet::<Arc<str>>::new();
+    let mut raw_transactions = HashMap::<Box<str>, Transaction>::new();
+    let mut catalog = SongCatalog::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let ParsedLine {
+            transaction,
+            item,
+            name,
+            artist,
+        } = column_indices.parse_line(trim_line_end(&line))?;
+        if let Some(name) = name {
+            if !catalog.contains_key(item.as_ref()) {
+                let song = Song {
+                    name: name.into(),
+                    artist: artist.map(Into::into),
+                    count: 0,
+                };
+                catalog.insert(item.to_string(), song);
+            }
+        }
+        let item = match interned_items.get(item.as_ref()) {
+            Some(item) => item.clone(),
+            None => {
+                let item: Arc<str> = item.into();
+                interned_items.insert(item.clone());
+                item
             }
+        };
+        match raw_transactions.get_mut(transaction) {
+            Some(items) => _ = items.insert(item),
+            None => _ = raw_transactions.insert(transaction.into(), [item].into()),
         }
-        raw_transactions
-            .entry(playlist_id.context("Line does not contain `pid` column")?)
-            .or_default()
-            .insert(track_name.context("Line does not contain `track_name` column")?);
     }
-    debug!("Got {} playlists.", raw_transactions.len());
+    debug!(
+        "Got {} `{}` transactions.",
+        raw_transactions.len(),
+        columns.transaction
+    );
+
+    let mut transactions: Vec<(Box<str>, Transaction)> = raw_transactions.into_iter().collect();
+    transactions.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(Dataset {
+        transactions: transactions.into_iter().map(|(_, items)| items).collect(),
+        catalog,
+    })
+}
+
+/// `line` without its `\n` or `\r\n`, like [`str::lines`].
+fn trim_line_end(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Mine rules from `transactions` and count the support of their items
+/// and itemsets.
+pub fn mine(transactions: &[Transaction], parameters: MiningParameters) -> MinedRules {
+    let n_transactions = transactions.len();
+    let mut item_counts = HashMap::<&str, usize>::new();
+    for item in transactions.iter().flatten() {
+        *item_counts.entry(item.as_ref()).or_default() += 1;
+    }
+
+    let mut counts: Vec<(String, usize)> = item_counts
+        .iter()
+        .map(|(item, count)| (item.to_string(), *count))
+        .collect();
+    counts.sort_unstable_by(|(a_item, a), (b_item, b)| b.cmp(a).then(a_item.cmp(b_item)));
+    let frequencies = ItemFrequencies {
+        n_transactions,
+        counts,
+    };
 
-    let (rules, _frequent_itemsets) = apriori(
-        raw_transactions.into_values().collect(),
-        MIN_SUPPORT,
-        MIN_CONFIDENCE,
-        MAX_LENGTH,
+    let MiningParameters {
+        min_support,
+        min_confidence,
+        max_length,
+    } = parameters;
+    let (rules, frequent_itemsets) = apriori(
+        transactions
+            .iter()
+            .map(|items| items.iter().map(AsRef::as_ref).collect())
+            .collect(),
+        min_support,
+        min_confidence,
+        max_length,
+    );
+    let itemsets = count_itemsets(
+        transactions.iter(),
+        &item_counts,
+        &frequent_itemsets,
+        &rules,
+        parameters,
+    );
+    debug!(
+        "Mined {} rules and counted {} frequent itemsets.",
+        rules.len(),
+        itemsets.counts.len()
     );
 
-    Ok(rules)
+    MinedRules {
+        rules,
+        frequencies,
+        itemsets,
+    }
 }
 
-fn get_playlist_id_and_track_name_index_in_header(header: &str) -> Result<(usize, usize)> {
-    let mut playlist_id_index = None;
-    let mut track_name_index = None;
-    for (index, attribute) in header.split(',').enumerate() {
-        match attribute {
-            "pid" => playlist_id_index = Some(index),
-            "track_name" => track_name_index = Some(index),
-            _ => {}
-        }
+/// The name to save the dataset at `url` as in the data directory:
+/// its last path segment, which must not be a model artifact.
+pub fn dataset_file_name(url: &str) -> Result<&str> {
+    let url_path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = match url_path.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, path)| path),
+        None => url_path,
+    };
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let artifacts = [
+        rules_path(""),
+        checkpoint_path(""),
+        metadata_path(""),
+        catalog_path(""),
+        frequencies_path(""),
+        itemsets_path(""),
+    ];
+    if matches!(file_name, "" | "." | "..")
+        || file_name.contains('\\')
+        || artifacts
+            .iter()
+            .any(|artifact| *artifact == Path::new(file_name))
+    {
+        bail!("Dataset URL `{url}` does not end in a usable file name.");
     }
-    Ok((
-        playlist_id_index.context("Dataset file has no `pid` column.")?,
-        track_name_index.context("Dataset file has no `track_name` column.")?,
-    ))
+    Ok(file_name)
 }
 
-fn read_url_file(url: &str, data_dir: impl AsRef<Path>) -> Result<String> {
-    let file_path = download(url, data_dir)?;
-    let content = read_file(file_path)?;
-    Ok(content)
+/// Check that `url` is an HTTP(S) URL with a host.
+pub fn check_http_url(url: &str) -> Result<()> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .with_context(|| format!("Dataset URL `{url}` has no scheme."))?;
+    if !["http", "https"]
+        .iter()
+        .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+    {
+        bail!("Dataset URL scheme `{scheme}` is neither `http` nor `https`.");
+    }
+    if rest.starts_with(['/', '?', '#']) || rest.is_empty() {
+        bail!("Dataset URL `{url}` has no host.");
+    }
+    Ok(())
 }
 
-fn download(url: &str, data_dir: impl AsRef<Path>) -> Result<PathBuf> {
-    let file_name = url
-        .split('/')
-        .last()
-        .expect("There should be at least one split.");
+fn download(
+    url: &str,
+    file_name: &str,
+    data_dir: impl AsRef<Path>,
+    recheck: bool,
+) -> Result<PathBuf> {
     let file_path = data_dir.as_ref().join(file_name);
     let file_path_str = file_path
         .to_str()
         .with_context(|| format!("File directory `{:?}` contains invalid UTF-8", file_path))?;
 
     debug!("Downloading `{}` to `{}`.", url, file_name);
-    let args = ["-o", file_path_str, "--check-certificate=false", "-c", url];
+    // A recheck asks the server with `If-Modified-Since` the download's time,
+    // which is stamped from the server's `Last-Modified`.
+    let mode: &[&str] = match recheck {
+        true => &["--conditional-get=true", "--allow-overwrite=true"],
+        false => &["-c"],
+    };
+    // `--` so that a URL starting with `-` is not read as an option.
+    let args = [
+        &[
+            "-o",
+            file_path_str,
+            "--check-certificate=false",
+            "--remote-time=true",
+        ],
+        mode,
+        &["--", url],
+    ]
+    .concat();
     let mut aria = Command::new("aria2c")
-        .args(args)
+        .args(&args)
         .spawn()
         .with_context(|| format!("Failed to spawn aria2c with {:?}.", args))?;
     let exit_status = aria.wait()?;
@@ -95,3 +364,13 @@ fn download(url: &str, data_dir: impl AsRef<Path>) -> Result<PathBuf> {
         Err(anyhow!("aria2c failed to download file `{}`", file_name))
     }
 }
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => (*message).into(),
+            Err(_) => "Panicked".into(),
+        },
+    }
+}
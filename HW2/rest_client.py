@@ -1,5 +1,6 @@
 #! /usr/bin/env python3
 import json
+import os
 import subprocess
 import sys
 import time
@@ -11,6 +12,7 @@ def print_help():
 <FLAG>:
     -h, --help: Print this help message
     -c, --continuous: Make continuous requests to the server and measure response changes
+Environment variable `API_KEY`, if set, is sent as the `X-API-Key` header.
 """
     )
 
@@ -42,6 +44,11 @@ def parse_args():
         sys.exit(1)
 
 
+def api_key_header_args():
+    api_key = os.environ.get("API_KEY")
+    return ["-H", f"X-API-Key: {api_key}"] if api_key else []
+
+
 def request(post_data, address):
     curl_json_request_args = [
         "curl",
@@ -49,6 +56,7 @@ def request(post_data, address):
         "POST",
         "-H",
         "Content-Type: application/json",
+        *api_key_header_args(),
         "-d",
         post_data,
         "--max-time",
@@ -0,0 +1,156 @@
+//! Send `POST /api/recommend` from many keep-alive connections at once and
+//! report the throughput and latencies.
+//!
+//! ```sh
+//! cargo run --release --example load_test -- 127.0.0.1:3000 "HUMBLE." "Mask Off"
+//! ```
+//!
+//! `CONNECTIONS` (default 64) and `SECONDS` (default 10) set the load,
+//! and `API_KEY`, if set, is sent as the `X-API-Key` header.
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    spawn,
+};
+
+#[derive(Debug, Default)]
+struct Tally {
+    ok: u64,
+    failed: u64,
+    latencies: Vec<Duration>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let address = args
+        .next()
+        .context("Usage: load_test <host:port> <song> [<song> …]")?;
+    let songs: Vec<String> = args.collect();
+    if songs.is_empty() {
+        bail!("At least one song must be provided.");
+    }
+    let connections = env_or("CONNECTIONS", 64)?;
+    let seconds = env_or("SECONDS", 10)?;
+
+    let body = serde_json::to_string(&serde_json::json!({ "songs": songs }))?;
+    let api_key = env::var("API_KEY")
+        .map(|key| format!("X-API-Key: {key}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        "POST /api/recommend HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\n{api_key}Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(seconds);
+    let tasks: Vec<_> = (0..connections)
+        .map(|_| spawn(connection(address.clone(), request.clone(), deadline)))
+        .collect();
+    let mut tally = Tally::default();
+    for task in tasks {
+        let Tally {
+            ok,
+            failed,
+            latencies,
+        } = task.await??;
+        tally.ok += ok;
+        tally.failed += failed;
+        tally.latencies.extend(latencies);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    tally.latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let index = ((tally.latencies.len() as f64 * p) as usize).min(tally.latencies.len() - 1);
+        tally.latencies[index]
+    };
+    println!(
+        "{connections} connections for {elapsed:.1}s: {} ok, {} failed, {:.0} requests/s",
+        tally.ok,
+        tally.failed,
+        tally.ok as f64 / elapsed
+    );
+    if !tally.latencies.is_empty() {
+        println!(
+            "latency p50 {:?}, p99 {:?}, max {:?}",
+            percentile(0.5),
+            percentile(0.99),
+            percentile(1.0)
+        );
+    }
+    Ok(())
+}
+
+fn env_or(name: &str, default: u64) -> Result<u64> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Invalid {name} `{value}`")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Send `request` repeatedly on one connection until `deadline`.
+async fn connection(address: String, request: String, deadline: Instant) -> Result<Tally> {
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .with_context(|| format!("Failed to connect to {address}"))?;
+    let mut tally = Tally::default();
+    let mut buffer = Vec::with_capacity(16 * 1024);
+    while Instant::now() < deadline {
+        let sent = Instant::now();
+        stream.write_all(request.as_bytes()).await?;
+        let status = read_response(&mut stream, &mut buffer).await?;
+        tally.latencies.push(sent.elapsed());
+        match status {
+            200 => tally.ok += 1,
+            _ => tally.failed += 1,
+        }
+    }
+    Ok(tally)
+}
+
+/// Read one response with a `Content-Length` and return its status code.
+async fn read_response(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<u16> {
+    buffer.clear();
+    let header_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        read_more(stream, buffer).await?;
+    };
+    let head = std::str::from_utf8(&buffer[..header_end]).context("Non-UTF-8 headers")?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .context("Invalid status line")?;
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().ok())?
+        })
+        .context("Response without Content-Length")?;
+    while buffer.len() < header_end + content_length {
+        read_more(stream, buffer).await?;
+    }
+    Ok(status)
+}
+
+async fn read_more(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<()> {
+    let mut chunk = [0; 8192];
+    match stream.read(&mut chunk).await? {
+        0 => bail!("Connection closed by the server"),
+        n => buffer.extend_from_slice(&chunk[..n]),
+    }
+    Ok(())
+}
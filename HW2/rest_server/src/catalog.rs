@@ -0,0 +1,163 @@
+use caseless::default_case_fold_str;
+use strsim::jaro_winkler;
+use unicode_normalization::UnicodeNormalization;
+
+use super::*;
+
+/// Minimum Jaro-Winkler similarity for an approximate song name match.
+const MIN_FUZZY_SIMILARITY: f64 = 0.9;
+
+/// Song display information and name lookup for the items in the rules.
+#[derive(Debug)]
+pub struct Catalog {
+    songs: SongCatalog,
+    /// IDs of songs sharing each normalized name, most frequent first.
+    ids_by_name: HashMap<String, Vec<String>>,
+}
+
+impl Catalog {
+    /// Index the names in `songs` and the items in `rules`,
+    /// the latter being song names themselves if mined without a catalog.
+    pub fn new(songs: SongCatalog, rules: &RuleIndex) -> Self {
+        let mut ids_by_name = HashMap::<String, Vec<String>>::new();
+        for (id, song) in &songs {
+            ids_by_name
+                .entry(normalize_name(&song.name))
+                .or_default()
+                .push(id.clone());
+        }
+        let rule_items: HashSet<&String> = rules
+            .iter()
+            .flat_map(|(antecedent, consequent)| antecedent.iter().chain(consequent))
+            .filter(|item| !songs.contains_key(*item))
+            .collect();
+        for item in rule_items {
+            ids_by_name
+                .entry(normalize_name(item))
+                .or_default()
+                .push(item.clone());
+        }
+
+        let count = |id: &String| songs.get(id).map_or(0, |song| song.count);
+        for ids in ids_by_name.values_mut() {
+            ids.sort_unstable_by(|a, b| count(b).cmp(&count(a)).then(a.cmp(b)));
+        }
+        Self { songs, ids_by_name }
+    }
+
+    /// Read the catalog, or index only the rules if mined without it.
+    #[instrument(skip(rules))]
+    pub fn read(catalog_path: &Path, rules: &RuleIndex) -> Result<Self> {
+        if !catalog_path.exists() {
+            warn!("No song catalog, serving items as song names.");
+            return Ok(Self::new(SongCatalog::new(), rules));
+        }
+        let songs = read_catalog(catalog_path)?;
+        info!(n_songs = songs.len(), "Read song catalog from file.");
+        Ok(Self::new(songs, rules))
+    }
+
+    /// Resolve a queried song ID or name to the item ID in the rules.
+    /// Names are compared after normalization,
+    /// and an ambiguous name resolves to the most frequent song with that name.
+    /// If no name matches, the most similar name is used if similar enough,
+    /// the first one alphabetically among equally similar ones.
+    pub fn resolve(&self, query: &str) -> Resolution {
+        if self.songs.contains_key(query) {
+            return self.resolution(query, query.into(), MatchKind::Exact);
+        }
+
+        let normalized = normalize_name(query);
+        if let Some(id) = self
+            .ids_by_name
+            .get(&normalized)
+            .and_then(|ids| ids.first())
+        {
+            let track = self.track(id.clone());
+            let kind = match track.name == query {
+                true => MatchKind::Exact,
+                false => MatchKind::Normalized,
+            };
+            return self.resolution(query, track.id, kind);
+        }
+
+        let best_match = self
+            .ids_by_name
+            .iter()
+            .map(|(name, ids)| (jaro_winkler(&normalized, name), name, ids))
+            .filter(|(similarity, _, _)| *similarity >= MIN_FUZZY_SIMILARITY)
+            .max_by(|(a, a_name, _), (b, b_name, _)| a.total_cmp(b).then(b_name.cmp(a_name)));
+        match best_match.and_then(|(_, _, ids)| ids.first()) {
+            Some(id) => self.resolution(query, id.clone(), MatchKind::Fuzzy),
+            None => Resolution {
+                query: query.into(),
+                track: None,
+                kind: MatchKind::Unmatched,
+            },
+        }
+    }
+
+    fn resolution(&self, query: &str, id: String, kind: MatchKind) -> Resolution {
+        Resolution {
+            query: query.into(),
+            track: Some(self.track(id)),
+            kind,
+        }
+    }
+
+    /// Number of transactions containing the song, if in the catalog.
+    pub fn count(&self, id: &str) -> Option<usize> {
+        self.songs.get(id).map(|song| song.count)
+    }
+
+    pub fn track(&self, id: String) -> Track {
+        match self.songs.get(&id) {
+            Some(Song { name, artist, .. }) => Track {
+                name: name.clone(),
+                artist: artist.clone(),
+                id,
+            },
+            None => Track {
+                name: id.clone(),
+                artist: None,
+                id,
+            },
+        }
+    }
+}
+
+/// Unicode NFKC, case folding, whitespace collapsing,
+/// and trimming of surrounding whitespace and punctuation.
+pub fn normalize_name(name: &str) -> String {
+    let folded = default_case_fold_str(&name.nfkc().collect::<String>());
+    folded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+        .into()
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Track {
+    pub id: String,
+    pub name: String,
+    pub artist: Option<String>,
+}
+
+/// Which catalog song a queried song was resolved to.
+#[derive(Clone, Debug, Serialize)]
+pub struct Resolution {
+    pub query: String,
+    pub track: Option<Track>,
+    pub kind: MatchKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Exact,
+    Normalized,
+    Fuzzy,
+    Unmatched,
+}
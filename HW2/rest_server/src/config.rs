@@ -0,0 +1,359 @@
+use std::{fmt, fs, net::SocketAddr, str::FromStr};
+
+use axum::http::HeaderValue;
+
+use crate::{
+    retry::RetryPolicy,
+    watch_file::{WatchBackend, WatchOptions},
+};
+
+use super::*;
+
+const RETRY_JITTER: f64 = 0.2;
+
+/// Settings of the REST API Server, from defaults, then a TOML file,
+/// then environment variables.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the ML Processor writes the model.
+    pub data_dir: PathBuf,
+    pub bind: BindAddress,
+    /// Maximum request body size.
+    pub max_body_bytes: usize,
+    /// Maximum request body size of `/api/recommend`.
+    pub max_recommend_body_bytes: usize,
+    /// Maximum number of songs in one recommendation request,
+    /// alone or in a batch.
+    pub max_request_songs: usize,
+    /// Maximum number of requests in one `/api/recommend/batch`.
+    pub max_batch_size: usize,
+    /// Maximum number of songs across the requests of one batch.
+    pub max_batch_songs: usize,
+    pub request_timeout_secs: u64,
+    /// How long to keep serving after SIGTERM or SIGINT with `/ready`
+    /// failing, so that load balancers stop routing here before draining.
+    pub pre_stop_delay_secs: u64,
+    /// How long in-flight requests may finish after the pre-stop delay.
+    pub drain_timeout_secs: u64,
+    /// How long file events settle before the model is reloaded.
+    pub reload_debounce_ms: u64,
+    pub watch_backend: WatchBackend,
+    /// How often to poll the checkpoint, unless `watch_backend` is native.
+    pub poll_interval_ms: u64,
+    /// Delay before the first retry of a failed reload,
+    /// doubling per consecutive failure.
+    pub retry_initial_ms: u64,
+    pub retry_max_ms: u64,
+    /// Consecutive failures of a reload task after which `/status`
+    /// reports the server as degraded.
+    pub degraded_after_failures: u32,
+    pub default_search_limit: usize,
+    pub max_search_limit: usize,
+    /// Requests per second each API key, or IP address without one, may make
+    /// to the `/api` routes on average; 0 disables rate limiting.
+    pub rate_limit_per_sec: f64,
+    /// Requests a client may make at once before `rate_limit_per_sec` applies.
+    pub rate_limit_burst: u32,
+    /// Requests to the `/api` routes handled at once across all clients.
+    pub max_concurrent_requests: usize,
+    pub log_format: LogFormat,
+    /// Origins allowed by CORS; `*` allows any, none disables CORS.
+    pub cors_origins: Vec<String>,
+    /// TOML file with API keys and HMAC secrets for bearer tokens;
+    /// without one, the `/api` routes are open.
+    pub auth_file: Option<PathBuf>,
+    /// Bearer token for the `/admin` routes, in addition to the auth file.
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: "ml-data".into(),
+            bind: BindAddress::Tcp(([0, 0, 0, 0], 3000).into()),
+            max_body_bytes: 64 * 1024,
+            max_recommend_body_bytes: 16 * 1024,
+            max_request_songs: 250,
+            max_batch_size: 100,
+            max_batch_songs: 1000,
+            request_timeout_secs: 10,
+            pre_stop_delay_secs: 5,
+            drain_timeout_secs: 20,
+            reload_debounce_ms: 500,
+            watch_backend: WatchBackend::Auto,
+            poll_interval_ms: 5000,
+            retry_initial_ms: 1000,
+            retry_max_ms: 60_000,
+            degraded_after_failures: 5,
+            default_search_limit: 10,
+            max_search_limit: 100,
+            rate_limit_per_sec: 0.0,
+            rate_limit_burst: 20,
+            max_concurrent_requests: 256,
+            log_format: LogFormat::Text,
+            cors_origins: Vec::new(),
+            auth_file: None,
+            admin_token: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read the TOML file at `path`, if any, apply overrides from `env`,
+    /// and validate the result.
+    pub fn load(path: Option<&Path>, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config {path:?}"))?;
+                toml::from_str(&content).with_context(|| format!("Invalid config {path:?}"))?
+            }
+            None => Self::default(),
+        };
+        config.override_from(env)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn override_from(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<()> {
+        fn set<T: FromStr>(
+            env: &impl Fn(&str) -> Option<String>,
+            name: &str,
+            field: &mut T,
+        ) -> Result<()>
+        where
+            T::Err: fmt::Display,
+        {
+            if let Some(value) = env(name) {
+                *field = value
+                    .parse()
+                    .map_err(|why| anyhow!("Invalid {name} `{value}`: {why}"))?;
+            }
+            Ok(())
+        }
+
+        set(&env, "DATA_DIR", &mut self.data_dir)?;
+        set(&env, "BIND_ADDRESS", &mut self.bind)?;
+        // `PORT` predates `BIND_ADDRESS` and only changes the TCP port.
+        if let Some(port) = env("PORT") {
+            let port = port
+                .parse()
+                .map_err(|why| anyhow!("Invalid PORT `{port}`: {why}"))?;
+            match &mut self.bind {
+                BindAddress::Tcp(address) => address.set_port(port),
+                BindAddress::Unix(_) => bail!("PORT conflicts with a Unix socket bind address."),
+            }
+        }
+        set(&env, "MAX_BODY_BYTES", &mut self.max_body_bytes)?;
+        set(
+            &env,
+            "MAX_RECOMMEND_BODY_BYTES",
+            &mut self.max_recommend_body_bytes,
+        )?;
+        set(&env, "MAX_REQUEST_SONGS", &mut self.max_request_songs)?;
+        set(&env, "MAX_BATCH_SIZE", &mut self.max_batch_size)?;
+        set(&env, "MAX_BATCH_SONGS", &mut self.max_batch_songs)?;
+        set(&env, "REQUEST_TIMEOUT_SECS", &mut self.request_timeout_secs)?;
+        set(&env, "PRE_STOP_DELAY_SECS", &mut self.pre_stop_delay_secs)?;
+        set(&env, "DRAIN_TIMEOUT_SECS", &mut self.drain_timeout_secs)?;
+        set(&env, "RELOAD_DEBOUNCE_MS", &mut self.reload_debounce_ms)?;
+        set(&env, "WATCH_BACKEND", &mut self.watch_backend)?;
+        set(&env, "POLL_INTERVAL_MS", &mut self.poll_interval_ms)?;
+        set(&env, "RETRY_INITIAL_MS", &mut self.retry_initial_ms)?;
+        set(&env, "RETRY_MAX_MS", &mut self.retry_max_ms)?;
+        set(
+            &env,
+            "DEGRADED_AFTER_FAILURES",
+            &mut self.degraded_after_failures,
+        )?;
+        set(&env, "DEFAULT_SEARCH_LIMIT", &mut self.default_search_limit)?;
+        set(&env, "MAX_SEARCH_LIMIT", &mut self.max_search_limit)?;
+        set(&env, "RATE_LIMIT_PER_SEC", &mut self.rate_limit_per_sec)?;
+        set(&env, "RATE_LIMIT_BURST", &mut self.rate_limit_burst)?;
+        set(
+            &env,
+            "MAX_CONCURRENT_REQUESTS",
+            &mut self.max_concurrent_requests,
+        )?;
+        set(&env, "LOG_FORMAT", &mut self.log_format)?;
+        if let Some(origins) = env("CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(Into::into)
+                .collect();
+        }
+        if let Some(path) = env("AUTH_FILE") {
+            self.auth_file = Some(path.into());
+        }
+        if let Some(token) = env("ADMIN_TOKEN") {
+            self.admin_token = Some(token);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.data_dir.as_os_str().is_empty() {
+            bail!("`data_dir` is empty.");
+        }
+        if self.max_body_bytes == 0 {
+            bail!("`max_body_bytes` must be positive.");
+        }
+        if self.max_recommend_body_bytes == 0 {
+            bail!("`max_recommend_body_bytes` must be positive.");
+        }
+        if self.max_request_songs == 0 {
+            bail!("`max_request_songs` must be positive.");
+        }
+        if self.max_batch_size == 0 {
+            bail!("`max_batch_size` must be positive.");
+        }
+        if self.max_batch_songs == 0 {
+            bail!("`max_batch_songs` must be positive.");
+        }
+        if self.request_timeout_secs == 0 {
+            bail!("`request_timeout_secs` must be positive.");
+        }
+        if self.poll_interval_ms == 0 {
+            bail!("`poll_interval_ms` must be positive.");
+        }
+        if self.retry_initial_ms == 0 || self.retry_initial_ms > self.retry_max_ms {
+            bail!("`retry_initial_ms` must be in 1..=`retry_max_ms`.");
+        }
+        if self.degraded_after_failures == 0 {
+            bail!("`degraded_after_failures` must be positive.");
+        }
+        if self.default_search_limit == 0 || self.default_search_limit > self.max_search_limit {
+            bail!("`default_search_limit` must be in 1..=`max_search_limit`.");
+        }
+        if !self.rate_limit_per_sec.is_finite() || self.rate_limit_per_sec < 0.0 {
+            bail!("`rate_limit_per_sec` must be a non-negative number.");
+        }
+        if self.rate_limit_burst == 0 {
+            bail!("`rate_limit_burst` must be positive.");
+        }
+        if self.max_concurrent_requests == 0 {
+            bail!("`max_concurrent_requests` must be positive.");
+        }
+        if self
+            .admin_token
+            .as_ref()
+            .is_some_and(|token| token.len() < 16)
+        {
+            bail!("`admin_token` must have at least 16 characters.");
+        }
+        for origin in &self.cors_origins {
+            let valid = origin == "*"
+                || (origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/')
+                    && HeaderValue::from_str(origin).is_ok();
+            if !valid {
+                bail!(
+                    "Invalid CORS origin `{origin}`, expected `*` or like `https://example.com`."
+                );
+            }
+        }
+        if self.cors_origins.len() > 1 && self.cors_origins.iter().any(|origin| origin == "*") {
+            bail!("CORS origin `*` cannot be combined with other origins.");
+        }
+        Ok(())
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn pre_stop_delay(&self) -> Duration {
+        Duration::from_secs(self.pre_stop_delay_secs)
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            initial: Duration::from_millis(self.retry_initial_ms),
+            max: Duration::from_millis(self.retry_max_ms),
+            jitter: RETRY_JITTER,
+            degraded_after: self.degraded_after_failures,
+        }
+    }
+
+    pub fn watch_options(&self) -> WatchOptions {
+        WatchOptions {
+            backend: self.watch_backend,
+            debounce: Duration::from_millis(self.reload_debounce_ms),
+            poll_interval: Duration::from_millis(self.poll_interval_ms),
+        }
+    }
+}
+
+/// A TCP socket address, IPv4 or IPv6 like `[::]:3000`,
+/// or a Unix socket path prefixed with `unix:`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("unix:") {
+            Some("") => bail!("Empty Unix socket path."),
+            Some(path) => Ok(Self::Unix(path.into())),
+            None => Ok(Self::Tcp(s.parse().with_context(|| {
+                format!("Invalid bind address `{s}`, expected like `0.0.0.0:3000`, `[::]:3000`, or `unix:/path`")
+            })?)),
+        }
+    }
+}
+
+impl TryFrom<String> for BindAddress {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl From<BindAddress> for String {
+    fn from(value: BindAddress) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unknown log format `{s}`, expected `text` or `json`."),
+        }
+    }
+}
@@ -1,37 +1,62 @@
 #![allow(clippy::type_complexity)]
-use anyhow::{Context, Result};
-use apriori::Rule;
+use anyhow::{anyhow, bail, Context, Result};
 use read_rules::RuleServer;
+use retry::Health;
 use serde::{Deserialize, Serialize};
 use shared::*;
 use std::{
     collections::{HashMap, HashSet},
-    fs::File,
     path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::{main, spawn, sync::oneshot, task::JoinHandle, time::sleep};
+use tokio::{runtime::Runtime, spawn, sync::oneshot, task::JoinHandle, time::sleep};
 use tracing::{debug, error, info, instrument, warn};
 
 use tokio_gen_server::actor::*;
 
+pub use config::{BindAddress, Config, LogFormat};
+pub use watch_file::WatchBackend;
+
+mod catalog;
+mod config;
 mod read_rules;
+mod retry;
+mod search;
 mod serve;
+#[cfg(test)]
+mod tests;
 mod watch_file;
 
 const ONE_SECOND: Duration = Duration::from_secs(1);
 
-#[main]
-#[instrument(skip(data_dir), fields(data_dir = ?data_dir.as_ref()))]
-pub async fn run(data_dir: impl AsRef<Path>, port: &str) -> Result<()> {
-    let rule_server = RuleServer::new(data_dir.as_ref().into());
+/// Serve until SIGTERM or SIGINT, then drain in-flight requests for up to
+/// the configured drain timeout and stop the actors.
+pub fn run(config: Config) -> Result<()> {
+    let runtime = Runtime::new()?;
+    let result = runtime.block_on(serve_and_stop(config));
+    // Dropping the runtime would wait for a running mining job.
+    runtime.shutdown_background();
+    result
+}
+
+#[instrument(skip(config), fields(data_dir = ?config.data_dir, bind = %config.bind))]
+async fn serve_and_stop(config: Config) -> Result<()> {
+    let health = Arc::new(Health::new(config.retry_policy()));
+    let rule_server = RuleServer::new(
+        config.data_dir.clone(),
+        config.watch_options(),
+        health.clone(),
+    );
+    let rules = rule_server.rules();
     let (rule_server_handle, mut rule_server_ref) = rule_server.spawn();
 
-    serve::serve(port, rule_server_ref.clone()).await?;
+    serve::serve(Arc::new(config), health, rules, rule_server_ref.clone()).await?;
 
+    info!("Stopping the rule server.");
     rule_server_ref.cancel();
     rule_server_handle.await??;
+    info!("Shut down.");
 
     Ok(())
 }
@@ -1,26 +1,23 @@
-use std::env;
+use std::{env, path::PathBuf};
 
 use anyhow::Result;
-use rest_server::run;
+use rest_server::{run, Config, LogFormat};
 use tracing::Level;
 use tracing_subscriber::EnvFilter;
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(Level::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
+    let config_path = env::var_os("CONFIG_FILE").map(PathBuf::from);
+    let config = Config::load(config_path.as_deref(), |name| env::var(name).ok())?;
 
-    let data_dir = match env::var("DATA_DIR") {
-        Ok(d) => Box::leak(d.into()),
-        Err(_) => "ml-data",
-    };
-    let port = match env::var("PORT") {
-        Ok(d) => Box::leak(d.into()),
-        Err(_) => "3000",
-    };
-    run(data_dir, port)
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        EnvFilter::builder()
+            .with_default_directive(Level::INFO.into())
+            .from_env_lossy(),
+    );
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    run(config)
 }
@@ -1,36 +1,63 @@
 use chrono::NaiveDateTime;
+use tokio::sync::watch;
 
 use super::*;
 
-use watch_file::FileWatcher;
+use catalog::Catalog;
+use retry::Health;
+use search::SongIndex;
+use watch_file::{FileWatcher, WatchOptions};
 
 pub struct RuleServer {
     data_dir: PathBuf,
+    watch_options: WatchOptions,
+    health: Arc<Health>,
     checkpoint_path: PathBuf,
     rules_path: PathBuf,
+    catalog_path: PathBuf,
+    frequencies_path: PathBuf,
     file_watcher: Option<(JoinHandle<Result<()>>, Ref<FileWatcher>)>,
     last_check: Instant,
-    rules_map: Option<Arc<RulesMap>>,
+    /// Publishes the current rules to [`Rules`].
+    rules_map: watch::Sender<Option<Arc<RulesMap>>>,
     timestamp_checked: i64,
 }
 
 impl RuleServer {
-    #[instrument]
-    pub fn new(data_dir: PathBuf) -> Self {
+    #[instrument(skip(health))]
+    pub fn new(data_dir: PathBuf, watch_options: WatchOptions, health: Arc<Health>) -> Self {
         Self {
             checkpoint_path: checkpoint_path(&data_dir),
             rules_path: rules_path(&data_dir),
+            catalog_path: catalog_path(&data_dir),
+            frequencies_path: frequencies_path(&data_dir),
             data_dir,
+            watch_options,
+            health,
             file_watcher: None,
             last_check: Instant::now(),
-            rules_map: None,
+            rules_map: watch::Sender::new(None),
             timestamp_checked: i64::MIN,
         }
     }
 
+    /// Reader of the rules this server publishes.
+    pub fn rules(&self) -> Rules {
+        Rules(self.rules_map.subscribe())
+    }
+
+    fn current_rules(&self) -> Option<Arc<RulesMap>> {
+        self.rules_map.borrow().clone()
+    }
+
     pub fn try_spawn_file_watcher(&mut self, env: Ref<Self>) -> Result<()> {
         let cancellation_token = env.cancellation_token.child_token();
-        let file_watcher = FileWatcher::new(self.data_dir.clone(), env);
+        let file_watcher = FileWatcher::new(
+            self.data_dir.clone(),
+            self.watch_options,
+            self.health.clone(),
+            env,
+        );
         let file_watcher = file_watcher.spawn_with_token(cancellation_token);
         self.file_watcher = Some(file_watcher);
         Ok(())
@@ -40,7 +67,7 @@ impl RuleServer {
 impl Actor for RuleServer {
     type CallMsg = ();
     type CastMsg = RuleServerMsg;
-    type Reply = Arc<RulesMap>;
+    type Reply = ();
 
     async fn init(&mut self, env: &mut Ref<Self>) -> Result<()> {
         env.cast(RuleServerMsg::InitFileWatcher).await?;
@@ -51,25 +78,30 @@ impl Actor for RuleServer {
     #[instrument(skip(self, msg, env))]
     async fn handle_cast(&mut self, msg: Self::CastMsg, env: &mut Ref<Self>) -> Result<()> {
         match msg {
-            RuleServerMsg::InitFileWatcher => {
-                if let Err(why) = self.try_spawn_file_watcher(env.clone()) {
-                    error!(?why, "Failed to spawn file watcher, retrying after sleep.");
-
-                    let mut env = env.clone();
+            RuleServerMsg::InitFileWatcher => match self.try_spawn_file_watcher(env.clone()) {
+                Ok(()) => self.health.succeeded("file_watcher_spawn"),
+                Err(why) => {
+                    error!(
+                        ?why,
+                        "Failed to spawn file watcher, retrying after backoff."
+                    );
+
+                    let (mut env, health) = (env.clone(), self.health.clone());
                     drop(spawn(async move {
-                        sleep(ONE_SECOND).await;
+                        health.backoff("file_watcher_spawn", &why).await;
                         env.cast(RuleServerMsg::InitFileWatcher).await
                     }))
                 }
-            }
+            },
 
             RuleServerMsg::WatchedFileChanged(when) if when > self.last_check => {
                 info!(?when, "File changed.");
                 self.last_check = when;
-                if let Some(rules_map) = &self.rules_map {
+                if let Some(rules_map) = self.current_rules() {
                     drop(spawn(check_checkpoint_or_retry(
                         self.checkpoint_path.clone(),
-                        rules_map.0,
+                        rules_map.timestamp,
+                        self.health.clone(),
                         env.clone(),
                     )));
                 }
@@ -91,20 +123,41 @@ impl Actor for RuleServer {
                 drop(spawn(update_rules_or_retry(
                     self.checkpoint_path.clone(),
                     self.rules_path.clone(),
-                    self.rules_map.as_ref().map_or(i64::MIN, |r| r.0),
+                    self.catalog_path.clone(),
+                    self.frequencies_path.clone(),
+                    self.current_rules().map_or(i64::MIN, |r| r.timestamp),
+                    self.health.clone(),
                     env.clone(),
                 )));
             }
             RuleServerMsg::ReadRules(_) => {}
 
-            RuleServerMsg::NewRules { rules_map, when } => match self.rules_map.as_ref() {
-                Some(current_map) if rules_map.0 <= current_map.0 => {}
+            RuleServerMsg::Reload(reply_sender) => {
+                info!("Forced reload.");
+                drop(spawn(force_reload(
+                    self.checkpoint_path.clone(),
+                    self.rules_path.clone(),
+                    self.catalog_path.clone(),
+                    self.frequencies_path.clone(),
+                    self.current_rules().map(|r| r.model_date.clone()),
+                    self.health.clone(),
+                    env.clone(),
+                    reply_sender,
+                )));
+            }
+
+            RuleServerMsg::NewRules {
+                rules_map,
+                when,
+                force,
+            } => match self.current_rules() {
+                Some(current_map) if !force && rules_map.timestamp <= current_map.timestamp => {}
                 _ => {
-                    let new_datetime = &rules_map.2;
+                    let new_datetime = &rules_map.model_date;
                     info!(?new_datetime, "New rules.");
 
-                    self.timestamp_checked = rules_map.0;
-                    self.rules_map = Some(Arc::new(rules_map));
+                    self.timestamp_checked = rules_map.timestamp;
+                    self.rules_map.send_replace(Some(Arc::from(rules_map)));
                     self.last_check = when;
                 }
             },
@@ -113,42 +166,73 @@ impl Actor for RuleServer {
         Ok(())
     }
 
-    #[instrument(skip(self, msg, env, response_sender))]
-    async fn handle_call(
-        &mut self,
-        msg: Self::CallMsg,
-        env: &mut Ref<Self>,
-        response_sender: oneshot::Sender<Self::Reply>,
-    ) -> Result<()> {
-        match &self.rules_map {
-            Some(rules_map) => _ = response_sender.send(Arc::clone(rules_map)),
-            None => {
-                warn!("Deferring query reply.");
-
-                let mut env = env.clone();
-                drop(spawn(async move {
-                    sleep(ONE_SECOND).await;
-                    _ = env.relay_call(msg, response_sender).await;
-                }));
-            }
+    /// Stop the file watcher before the rule server exits.
+    async fn before_exit(&mut self, _env: &mut Ref<Self>) -> Result<()> {
+        if let Some((handle, mut file_watcher_ref)) = self.file_watcher.take() {
+            info!("Stopping the file watcher.");
+            file_watcher_ref.cancel();
+            handle.await??;
         }
-
         Ok(())
     }
 }
 
-pub struct RulesMap(
-    pub i64,
-    pub HashMap<Vec<String>, HashSet<String>>,
-    pub String,
-);
+/// The current rules, read by handlers without messaging the [`RuleServer`],
+/// which publishes each new model here.
+#[derive(Clone)]
+pub struct Rules(watch::Receiver<Option<Arc<RulesMap>>>);
+
+impl Rules {
+    /// Always `rules_map`, without a rule server.
+    #[cfg(test)]
+    pub fn fixed(rules_map: RulesMap) -> Self {
+        Self(watch::channel(Some(Arc::new(rules_map))).1)
+    }
+
+    /// The current rules, waiting for the first model to be read.
+    pub async fn current(&self) -> Result<Arc<RulesMap>> {
+        if let Some(rules_map) = self.0.borrow().as_ref() {
+            return Ok(rules_map.clone());
+        }
+        debug!("Waiting for the first model.");
+        let mut receiver = self.0.clone();
+        let rules_map = receiver
+            .wait_for(Option::is_some)
+            .await
+            .context("Rule server stopped")?;
+        Ok(rules_map.clone().expect("Waited for rules"))
+    }
+}
+
+pub struct RulesMap {
+    pub timestamp: i64,
+    pub rules: RuleIndex,
+    pub model_date: String,
+    pub catalog: Catalog,
+    pub song_index: SongIndex,
+    /// Item IDs, most frequent first, for filling in recommendations.
+    pub popular_items: Vec<String>,
+}
 
 impl RulesMap {
-    pub fn new(timestamp: i64, rules_map: HashMap<Vec<String>, HashSet<String>>) -> Self {
-        let data_datetime = NaiveDateTime::from_timestamp_nanos(timestamp)
+    pub fn new(
+        timestamp: i64,
+        rules: RuleIndex,
+        catalog: Catalog,
+        popular_items: Vec<String>,
+    ) -> Self {
+        let model_date = NaiveDateTime::from_timestamp_nanos(timestamp)
             .unwrap()
             .to_string();
-        Self(timestamp, rules_map, data_datetime)
+        let song_index = SongIndex::new(&rules, &catalog);
+        Self {
+            timestamp,
+            rules,
+            model_date,
+            catalog,
+            song_index,
+            popular_items,
+        }
     }
 }
 
@@ -157,21 +241,38 @@ pub enum RuleServerMsg {
     WatchedFileChanged(Instant),
     NewCheckpoint(i64),
     ReadRules(Instant),
-    NewRules { rules_map: RulesMap, when: Instant },
+    /// Reread the model regardless of the checkpoint and reply the outcome.
+    Reload(oneshot::Sender<Result<ReloadOutcome>>),
+    NewRules {
+        rules_map: Box<RulesMap>,
+        when: Instant,
+        /// Replace the current rules even if they are not older.
+        force: bool,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReloadOutcome {
+    pub model_date: String,
+    pub previous_model_date: Option<String>,
 }
 
 async fn check_checkpoint_or_retry(
     checkpoint_path: PathBuf,
     old_timestamp: i64,
+    health: Arc<Health>,
     mut server_ref: Ref<RuleServer>,
 ) {
-    if let Err(why) = try_check_checkpoint(&checkpoint_path, old_timestamp, &mut server_ref).await {
-        error!(?why, "Failed to check checkpoint.");
-
-        let when_failed = Instant::now();
-        sleep(ONE_SECOND).await;
-        let retry_event = RuleServerMsg::WatchedFileChanged(when_failed);
-        _ = server_ref.cast(retry_event).await
+    match try_check_checkpoint(&checkpoint_path, old_timestamp, &mut server_ref).await {
+        Ok(()) => health.succeeded("checkpoint"),
+        Err(why) => {
+            error!(?why, "Failed to check checkpoint.");
+
+            let when_failed = Instant::now();
+            health.backoff("checkpoint", &why).await;
+            let retry_event = RuleServerMsg::WatchedFileChanged(when_failed);
+            _ = server_ref.cast(retry_event).await
+        }
     }
 }
 
@@ -192,74 +293,130 @@ async fn try_check_checkpoint(
 async fn update_rules_or_retry(
     checkpoint_path: PathBuf,
     rules_path: PathBuf,
+    catalog_path: PathBuf,
+    frequencies_path: PathBuf,
     old_timestamp: i64,
+    health: Arc<Health>,
     mut server_ref: Ref<RuleServer>,
 ) {
-    if let Err(why) = try_update_rules(
+    let result = try_update_rules(
         &checkpoint_path,
         &rules_path,
+        &catalog_path,
+        &frequencies_path,
         old_timestamp,
         &mut server_ref,
     )
-    .await
-    {
-        error!(?why, "Failed to update rules.");
-
-        let when_fail = Instant::now();
-        sleep(ONE_SECOND).await;
-        let retry_event = RuleServerMsg::ReadRules(when_fail);
-        _ = server_ref.cast(retry_event).await
+    .await;
+    match result {
+        Ok(()) => health.succeeded("reload"),
+        Err(why) => {
+            error!(?why, "Failed to update rules.");
+
+            let when_fail = Instant::now();
+            health.backoff("reload", &why).await;
+            let retry_event = RuleServerMsg::ReadRules(when_fail);
+            _ = server_ref.cast(retry_event).await
+        }
     }
 }
 
 async fn try_update_rules(
     checkpoint_path: &Path,
     rules_path: &Path,
+    catalog_path: &Path,
+    frequencies_path: &Path,
     old_timestamp: i64,
     server_ref: &mut Ref<RuleServer>,
 ) -> Result<()> {
     let timestamp = checkpoint_timestamp(checkpoint_path).context("Checkpoint timestamp")?;
     if timestamp > old_timestamp {
         let when = Instant::now();
-        let rules_map = make_rules_map(rules_path).context("Read rules from file")?;
+        let rules_map = read_model(timestamp, rules_path, catalog_path, frequencies_path)?;
         let new_rules_event = RuleServerMsg::NewRules {
-            rules_map: RulesMap::new(timestamp, rules_map),
+            rules_map: Box::new(rules_map),
             when,
+            force: false,
         };
         _ = server_ref.cast(new_rules_event).await;
     }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn force_reload(
+    checkpoint_path: PathBuf,
+    rules_path: PathBuf,
+    catalog_path: PathBuf,
+    frequencies_path: PathBuf,
+    previous_model_date: Option<String>,
+    health: Arc<Health>,
+    mut server_ref: Ref<RuleServer>,
+    reply_sender: oneshot::Sender<Result<ReloadOutcome>>,
+) {
+    let when = Instant::now();
+    let result = checkpoint_timestamp(&checkpoint_path)
+        .context("Checkpoint timestamp")
+        .and_then(|timestamp| read_model(timestamp, &rules_path, &catalog_path, &frequencies_path));
+    let reply = match result {
+        Ok(rules_map) => {
+            health.succeeded("reload");
+            let outcome = ReloadOutcome {
+                model_date: rules_map.model_date.clone(),
+                previous_model_date,
+            };
+            let new_rules_event = RuleServerMsg::NewRules {
+                rules_map: Box::new(rules_map),
+                when,
+                force: true,
+            };
+            _ = server_ref.cast(new_rules_event).await;
+            Ok(outcome)
+        }
+        Err(why) => {
+            error!(?why, "Failed to force reload.");
+            Err(why)
+        }
+    };
+    _ = reply_sender.send(reply);
+}
+
+fn read_model(
+    timestamp: i64,
+    rules_path: &Path,
+    catalog_path: &Path,
+    frequencies_path: &Path,
+) -> Result<RulesMap> {
+    let rules_map = make_rules_map(rules_path).context("Read rules from file")?;
+    let catalog = Catalog::read(catalog_path, &rules_map).context("Read song catalog from file")?;
+    let popular_items =
+        read_popular_items(frequencies_path).context("Read item frequencies from file")?;
+    Ok(RulesMap::new(timestamp, rules_map, catalog, popular_items))
+}
+
 fn checkpoint_timestamp(checkpoint_path: impl AsRef<Path>) -> Result<i64> {
-    read_file(&checkpoint_path)
-        .with_context(|| format!("Read {:?}", checkpoint_path.as_ref()))?
-        .split_whitespace()
-        .nth(2)
-        .context("No timestamp found")?
-        .parse()
-        .context("Failed to parse timestamp number")
+    let checkpoint = Checkpoint::read(&checkpoint_path)
+        .with_context(|| format!("Read {:?}", checkpoint_path.as_ref()))?;
+    Ok(checkpoint.timestamp)
 }
 
+/// Item IDs, most frequent first,
+/// or none if the rules were mined without item frequencies.
 #[instrument]
-fn make_rules_map(rules_path: &Path) -> Result<HashMap<Vec<String>, HashSet<String>>> {
-    let file = File::open(rules_path)?;
-    let rules: Vec<Rule> = bincode::deserialize_from(file)?;
+fn read_popular_items(frequencies_path: &Path) -> Result<Vec<String>> {
+    if !frequencies_path.exists() {
+        warn!("No item frequencies, recommending from rules only.");
+        return Ok(Vec::new());
+    }
+    let ItemFrequencies { counts, .. } = read_frequencies(frequencies_path)?;
+    info!(n_items = counts.len(), "Read item frequencies from file.");
+    Ok(counts.into_iter().map(|(item, _)| item).collect())
+}
+
+#[instrument]
+fn make_rules_map(rules_path: &Path) -> Result<RuleIndex> {
+    let rules = read_rules(rules_path)?;
     info!(n_rules = rules.len(), "Read rules from file.");
 
-    Ok(rules
-        .into_iter()
-        .map(
-            |Rule {
-                 antecedent,
-                 consequent,
-                 confidence: _,
-                 lift: _,
-             }| {
-                let mut antecedent_vec: Vec<_> = antecedent.into_iter().collect();
-                antecedent_vec.sort_unstable();
-                (antecedent_vec, consequent)
-            },
-        )
-        .collect())
+    Ok(index_rules(rules))
 }
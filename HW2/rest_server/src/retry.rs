@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use rand::Rng;
+
+use super::*;
+
+/// Exponential backoff for retrying failed background tasks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay after the first failure.
+    pub initial: Duration,
+    /// Cap on the delay.
+    pub max: Duration,
+    /// Fraction of the delay randomly taken off, in `0.0..=1.0`,
+    /// so that retries of separate tasks spread out.
+    pub jitter: f64,
+    /// Consecutive failures of a task after which the server is degraded.
+    pub degraded_after: u32,
+}
+
+impl RetryPolicy {
+    /// Delay after `failures` consecutive failures,
+    /// with `jitter_sample` in `0.0..1.0` scaling the jitter.
+    pub fn delay(&self, failures: u32, jitter_sample: f64) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31) as i32;
+        let delay = self.initial.mul_f64(2f64.powi(exponent)).min(self.max);
+        delay.mul_f64(1.0 - self.jitter * jitter_sample)
+    }
+}
+
+/// Consecutive failures of the background tasks,
+/// shared by the actors and the status endpoint.
+#[derive(Debug)]
+pub struct Health {
+    policy: RetryPolicy,
+    tasks: Mutex<BTreeMap<&'static str, TaskHealth>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TaskHealth {
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthStatus {
+    /// `degraded` once any task failed `degraded_after` times in a row.
+    pub status: &'static str,
+    pub tasks: BTreeMap<&'static str, TaskHealth>,
+}
+
+impl HealthStatus {
+    /// The status without the error messages,
+    /// which may reveal paths and other internals.
+    pub fn without_errors(mut self) -> Self {
+        for task in self.tasks.values_mut() {
+            task.last_error = None;
+        }
+        self
+    }
+}
+
+impl Health {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            tasks: Mutex::default(),
+        }
+    }
+
+    /// Record a failure of `task` and return how long to wait before retrying.
+    pub fn failed(&self, task: &'static str, error: &anyhow::Error) -> Duration {
+        let mut tasks = self.tasks.lock().expect("Health lock poisoned");
+        let health = tasks.entry(task).or_default();
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        health.last_error = Some(format!("{error:#}"));
+        let failures = health.consecutive_failures;
+        if failures == self.policy.degraded_after {
+            warn!(task, failures, "Task keeps failing, degraded.");
+        }
+        self.policy.delay(failures, rand::thread_rng().gen())
+    }
+
+    /// Record a failure of `task` and wait before retrying.
+    pub async fn backoff(&self, task: &'static str, error: &anyhow::Error) {
+        let delay = self.failed(task, error);
+        debug!(task, ?delay, "Retrying after backoff.");
+        sleep(delay).await;
+    }
+
+    pub fn succeeded(&self, task: &'static str) {
+        let mut tasks = self.tasks.lock().expect("Health lock poisoned");
+        if let Some(health) = tasks.get_mut(task) {
+            if health.consecutive_failures >= self.policy.degraded_after {
+                info!(task, "Task recovered.");
+            }
+            *health = TaskHealth::default();
+        }
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        let tasks = self.tasks.lock().expect("Health lock poisoned").clone();
+        let degraded = tasks
+            .values()
+            .any(|health| health.consecutive_failures >= self.policy.degraded_after);
+        HealthStatus {
+            status: if degraded { "degraded" } else { "ok" },
+            tasks,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use catalog::{normalize_name, Catalog, Track};
+
+use super::*;
+
+/// Songs in the rules sorted by normalized name for prefix search.
+#[derive(Debug)]
+pub struct SongIndex {
+    entries: Vec<SongEntry>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SongEntry {
+    #[serde(skip)]
+    normalized_name: String,
+    #[serde(flatten)]
+    pub track: Track,
+    /// Number of playlists containing the song per the song catalog,
+    /// or number of rules containing it if mined without the catalog.
+    pub frequency: usize,
+}
+
+impl SongIndex {
+    pub fn new(rules: &RuleIndex, catalog: &Catalog) -> Self {
+        let mut rule_counts = HashMap::<&String, usize>::new();
+        for (antecedent, consequent) in rules {
+            for item in antecedent.iter().chain(consequent) {
+                *rule_counts.entry(item).or_default() += 1;
+            }
+        }
+
+        let mut entries: Vec<SongEntry> = rule_counts
+            .into_iter()
+            .map(|(id, rule_count)| {
+                let frequency = catalog.count(id).unwrap_or(rule_count);
+                let track = catalog.track(id.clone());
+                SongEntry {
+                    normalized_name: normalize_name(&track.name),
+                    track,
+                    frequency,
+                }
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.normalized_name.cmp(&b.normalized_name));
+        Self { entries }
+    }
+
+    /// Up to `limit` songs whose normalized name starts with the normalized
+    /// `prefix`, most frequent first.
+    pub fn search(&self, prefix: &str, limit: usize) -> Vec<SongEntry> {
+        let prefix = normalize_name(prefix);
+        let start = self
+            .entries
+            .partition_point(|entry| entry.normalized_name < prefix);
+        let mut matches: Vec<&SongEntry> = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.normalized_name.starts_with(&prefix))
+            .collect();
+        matches.sort_by(|a, b| {
+            b.frequency
+                .cmp(&a.frequency)
+                .then_with(|| a.track.name.cmp(&b.track.name))
+        });
+        matches.into_iter().take(limit).cloned().collect()
+    }
+}
@@ -1,30 +1,280 @@
+use std::{
+    fmt::Debug,
+    fs,
+    future::{pending, Future},
+    os::unix::fs::FileTypeExt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use axum::{
-    http::StatusCode,
+    extract::{connect_info::Connected, DefaultBodyLimit, Query, Request},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderName, Method, StatusCode,
+    },
+    middleware::{from_fn, Next},
     routing::{get, post},
+    serve::{IncomingStream, Listener},
     Json, Router,
 };
-use itertools::Itertools;
+use tokio::{
+    net::{TcpListener, UnixListener},
+    select,
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    task::spawn_blocking,
+};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    timeout::TimeoutLayer,
+};
 
-use self::read_rules::RulesMap;
+use self::{
+    catalog::{Resolution, Track},
+    read_rules::{RuleServerMsg, Rules, RulesMap},
+    retry::{Health, HealthStatus},
+    search::SongEntry,
+};
 
 use super::*;
 
+mod admin;
+pub mod auth;
 mod error;
+pub mod limit;
 
+use auth::{require_scope, Auth, Scope, API_KEY_HEADER};
 use error::AppError;
+use limit::{enforce_limits, Client, ClientAddr, Limits};
 
-#[instrument(skip(query_server_ref))]
-pub async fn serve(port: &str, query_server_ref: Ref<RuleServer>) -> Result<()> {
+#[instrument(skip_all, fields(bind = %config.bind))]
+pub async fn serve(
+    config: Arc<Config>,
+    health: Arc<Health>,
+    rules: Rules,
+    rule_server_ref: Ref<RuleServer>,
+) -> Result<()> {
     info!("Starting server.");
-    let app = Router::new().route("/", get(home_handler)).route(
-        "/api/recommend",
-        post(|request| async move { query_handler(request, query_server_ref.clone()).await }),
-    );
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
-    axum::serve(listener, app).await?;
+    let ready = Arc::new(AtomicBool::new(true));
+    let app = app(
+        config.clone(),
+        health,
+        rules,
+        rule_server_ref,
+        ready.clone(),
+    )?;
+    let (pre_stop_delay, drain_timeout) = (config.pre_stop_delay(), config.drain_timeout());
+    match &config.bind {
+        BindAddress::Tcp(address) => {
+            let listener = TcpListener::bind(address).await?;
+            serve_until_drained(
+                listener,
+                app,
+                ready,
+                shutdown_signal(),
+                pre_stop_delay,
+                drain_timeout,
+            )
+            .await
+        }
+        BindAddress::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to bind Unix socket {path:?}"))?;
+            serve_until_drained(
+                listener,
+                app,
+                ready,
+                shutdown_signal(),
+                pre_stop_delay,
+                drain_timeout,
+            )
+            .await
+        }
+    }
+}
+
+/// All routes, with `/ready` failing once `ready` is cleared.
+pub fn app(
+    config: Arc<Config>,
+    health: Arc<Health>,
+    rules: Rules,
+    rule_server_ref: Ref<RuleServer>,
+    ready: Arc<AtomicBool>,
+) -> Result<Router> {
+    let query_config = config.clone();
+    let (batch_rules, batch_config) = (rules.clone(), config.clone());
+    let search_rules = rules.clone();
+    let search_config = config.clone();
+    let auth = Arc::new(Auth::from_config(&config)?);
+    let api_auth = auth.clone();
+    let limits = Arc::new(Limits::new(&config));
+    let batch_limits = limits.clone();
+    let (admin_health, admin_ready) = (health.clone(), ready.clone());
+    let api =
+        Router::new()
+            .route(
+                "/api/recommend",
+                post(|request| async move { query_handler(request, &rules, &query_config).await })
+                    .layer(DefaultBodyLimit::max(config.max_recommend_body_bytes)),
+            )
+            .route(
+                "/api/recommend/batch",
+                post(|client, request| async move {
+                    batch_handler(client, request, &batch_rules, &batch_config, &batch_limits).await
+                }),
+            )
+            .route(
+                "/api/songs",
+                get(|request| async move {
+                    search_handler(request, &search_rules, &search_config).await
+                }),
+            )
+            // Authenticate first so that the rate limit applies per API key.
+            .route_layer(from_fn(move |request: Request, next: Next| {
+                enforce_limits(limits.clone(), request, next)
+            }))
+            .route_layer(from_fn(move |request: Request, next: Next| {
+                require_scope(api_auth.clone(), Scope::Recommend, request, next)
+            }));
+    let app = Router::new()
+        .route("/", get(home_handler))
+        .route("/ready", get(|| async move { ready_handler(&ready) }))
+        .route("/status", get(|| async move { status_handler(&health) }))
+        .merge(api)
+        .nest(
+            "/admin",
+            admin::router(
+                config.clone(),
+                auth,
+                admin_health,
+                rule_server_ref,
+                admin_ready,
+            ),
+        )
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            config.request_timeout(),
+        ))
+        .layer(cors_layer(&config.cors_origins));
+    Ok(app)
+}
+
+/// Serve `app` until `shutdown` completes, then fail `/ready` while still
+/// serving for `pre_stop_delay`, then stop accepting connections and let
+/// in-flight requests finish for up to `drain_timeout`.
+pub async fn serve_until_drained<L>(
+    listener: L,
+    app: Router,
+    ready: Arc<AtomicBool>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    pre_stop_delay: Duration,
+    drain_timeout: Duration,
+) -> Result<()>
+where
+    L: Listener,
+    L::Addr: Debug,
+    for<'a> ClientAddr: Connected<IncomingStream<'a, L>>,
+{
+    let (draining_sender, draining_receiver) = oneshot::channel();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<ClientAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown.await;
+        ready.store(false, Ordering::Relaxed);
+        info!(?pre_stop_delay, "Shutting down, failing readiness.");
+        sleep(pre_stop_delay).await;
+        info!(?drain_timeout, "Draining in-flight requests.");
+        _ = draining_sender.send(());
+    });
+    let drain_deadline = async {
+        if draining_receiver.await.is_ok() {
+            sleep(drain_timeout).await;
+        } else {
+            pending().await
+        }
+    };
+    select! {
+        result = server => result?,
+        () = drain_deadline => warn!("Drain timeout elapsed, dropping the remaining requests."),
+    }
     Ok(())
 }
 
+/// Remove a socket left behind by a previous run, which would fail the bind.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            debug!(?path, "Removing stale Unix socket.");
+            fs::remove_file(path).with_context(|| format!("Failed to remove {path:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// CORS for the configured origins, which are validated at startup.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = match origins {
+        [any] if any == "*" => AllowOrigin::any(),
+        origins => AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| origin.parse().expect("Validated CORS origin")),
+        ),
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            HeaderName::from_static(API_KEY_HEADER),
+        ])
+}
+
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(why) = ctrl_c().await {
+            error!(?why, "Failed to listen for SIGINT.");
+            pending().await
+        }
+    };
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => _ = terminate.recv().await,
+            Err(why) => {
+                error!(?why, "Failed to listen for SIGTERM.");
+                pending().await
+            }
+        }
+    };
+    select! {
+        () = interrupt => info!("Received SIGINT."),
+        () = terminate => info!("Received SIGTERM."),
+    }
+}
+
+/// Readiness probe, failing once shutting down so that no new requests
+/// are routed here.
+fn ready_handler(ready: &AtomicBool) -> (StatusCode, &'static str) {
+    match ready.load(Ordering::Relaxed) {
+        true => (StatusCode::OK, "ready"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+    }
+}
+
+/// Consecutive failures of the background tasks,
+/// and whether they keep failing, without the errors,
+/// which `/admin/status` reports.
+fn status_handler(health: &Health) -> Json<HealthStatus> {
+    Json(health.status().without_errors())
+}
+
 async fn home_handler() -> &'static str {
     info!("Requested /.");
     "/"
@@ -32,57 +282,219 @@ async fn home_handler() -> &'static str {
 
 async fn query_handler(
     Json(request): Json<RecommendationRequest>,
-    mut query_server_ref: Ref<RuleServer>,
+    rules: &Rules,
+    config: &Config,
 ) -> Result<Json<RecommendationResponse>, AppError> {
     info!(?request);
-    let rules_map = query_server_ref.call(()).await?;
-
-    let songs = recommend_songs(request.songs, &rules_map);
-    let response = RecommendationResponse::new(songs, rules_map.2.clone());
+    let rules_map = rules.current().await?;
+    let max_songs = config.max_request_songs;
+    let response = spawn_blocking(move || recommend_for(&request, &rules_map, max_songs))
+        .await?
+        .map_err(|why| AppError::new(StatusCode::BAD_REQUEST, why))?;
     Ok(Json(response))
 }
 
+/// Answer every request in the batch against the same rules,
+/// with an error for each invalid one rather than for the batch.
+/// Each request in the batch takes a rate-limit token.
+async fn batch_handler(
+    client: Client,
+    Json(requests): Json<Vec<serde_json::Value>>,
+    rules: &Rules,
+    config: &Config,
+    limits: &Limits,
+) -> Result<Json<BatchResponse>, AppError> {
+    info!(n_requests = requests.len(), "Batch request.");
+    if requests.len() > config.max_batch_size {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Batch of {} requests exceeds the maximum of {}.",
+                requests.len(),
+                config.max_batch_size
+            ),
+        ));
+    }
+    let n_songs: usize = requests
+        .iter()
+        .filter_map(|request| request.get("songs")?.as_array().map(Vec::len))
+        .sum();
+    if n_songs > config.max_batch_songs {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Batch of {n_songs} songs exceeds the maximum of {}.",
+                config.max_batch_songs
+            ),
+        ));
+    }
+    // The rate limit already took a token for the batch itself.
+    limits.charge(&client, requests.len().saturating_sub(1));
+
+    let rules_map = rules.current().await?;
+    let batch_rules = rules_map.clone();
+    let max_songs = config.max_request_songs;
+    let results =
+        spawn_blocking(move || recommend_batch(requests, &batch_rules, max_songs)).await?;
+    Ok(Json(BatchResponse {
+        results,
+        version: crate_version!(),
+        model_date: rules_map.model_date.clone(),
+    }))
+}
+
+/// Answer each request, failing those with more than `max_songs` songs.
+pub fn recommend_batch(
+    requests: Vec<serde_json::Value>,
+    rules_map: &RulesMap,
+    max_songs: usize,
+) -> Vec<BatchResult> {
+    requests
+        .into_iter()
+        .map(|request| {
+            serde_json::from_value(request)
+                .context("Invalid request")
+                .and_then(|request| recommend_for(&request, rules_map, max_songs))
+                .map_or_else(
+                    |why| BatchResult::Error(format!("{why:#}")),
+                    BatchResult::Ok,
+                )
+        })
+        .collect()
+}
+
+/// Recommend songs for `request`, which may hold up to `max_songs` songs.
+pub fn recommend_for(
+    request: &RecommendationRequest,
+    rules_map: &RulesMap,
+    max_songs: usize,
+) -> Result<RecommendationResponse> {
+    if request.limit == Some(0) {
+        bail!("`limit` must be positive.");
+    }
+    if request.songs.len() > max_songs {
+        bail!(
+            "Request of {} songs exceeds the maximum of {max_songs}.",
+            request.songs.len()
+        );
+    }
+    let resolved: Vec<Resolution> = request
+        .songs
+        .iter()
+        .map(|song| rules_map.catalog.resolve(song))
+        .collect();
+    let query = resolved
+        .iter()
+        .filter_map(|resolution| resolution.track.as_ref().map(|track| track.id.clone()))
+        .collect();
+    let tracks = recommend_songs(query, rules_map)
+        .into_iter()
+        .take(request.limit.unwrap_or(usize::MAX))
+        .map(|(id, source)| Recommendation {
+            track: rules_map.catalog.track(id),
+            source,
+        })
+        .collect();
+    Ok(RecommendationResponse::new(
+        tracks,
+        resolved,
+        rules_map.model_date.clone(),
+    ))
+}
+
+async fn search_handler(
+    Query(request): Query<SongSearchRequest>,
+    rules: &Rules,
+    config: &Config,
+) -> Result<Json<SongSearchResponse>, AppError> {
+    info!(?request);
+    let rules_map = rules.current().await?;
+
+    let limit = request
+        .limit
+        .unwrap_or(config.default_search_limit)
+        .min(config.max_search_limit);
+    let songs = rules_map.song_index.search(&request.prefix, limit);
+    Ok(Json(SongSearchResponse {
+        songs,
+        version: crate_version!(),
+        model_date: rules_map.model_date.clone(),
+    }))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SongSearchRequest {
+    #[serde(default)]
+    pub prefix: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SongSearchResponse {
+    pub songs: Vec<SongEntry>,
+    pub version: &'static str,
+    pub model_date: String,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct RecommendationRequest {
+    /// Song IDs or names.
     pub songs: Vec<String>,
+    /// Maximum number of recommendations.
+    pub limit: Option<usize>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct RecommendationResponse {
+    /// Names of the recommended `tracks`.
     pub songs: Vec<String>,
+    pub tracks: Vec<Recommendation>,
+    /// Which catalog song each queried song was resolved to.
+    pub resolved: Vec<Resolution>,
     pub version: &'static str,
     pub model_date: String,
 }
 
 impl RecommendationResponse {
-    pub fn new(songs: Vec<String>, model_date: String) -> Self {
+    pub fn new(tracks: Vec<Recommendation>, resolved: Vec<Resolution>, model_date: String) -> Self {
         Self {
-            songs,
+            songs: tracks
+                .iter()
+                .map(|track| track.track.name.clone())
+                .collect(),
+            tracks,
+            resolved,
             version: crate_version!(),
             model_date,
         }
     }
 }
 
-#[instrument(skip(rules_map))]
-fn recommend_songs(mut query: Vec<String>, rules_map: &RulesMap) -> Vec<String> {
-    query.sort_unstable();
-    query.dedup();
-    let mut response = HashSet::with_capacity(MAX_LENGTH * 2);
-
-    'combinations: for length in (1..(query.len().min(MAX_LENGTH) + 1)).rev() {
-        for mut combination in query.iter().cloned().combinations(length) {
-            combination.sort_unstable();
-            if let Some(predictions) = rules_map.1.get(&combination) {
-                response.extend(predictions);
-                if response.len() >= MAX_LENGTH {
-                    debug!(length, "Got enough predictions.");
-                    break 'combinations;
-                }
-            }
-        }
-    }
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchResponse {
+    /// One per request, in order.
+    pub results: Vec<BatchResult>,
+    pub version: &'static str,
+    pub model_date: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResult {
+    Ok(RecommendationResponse),
+    Error(String),
+}
 
-    debug!("Sending response.");
-    response.into_iter().cloned().collect()
+#[derive(Clone, Debug, Serialize)]
+pub struct Recommendation {
+    #[serde(flatten)]
+    pub track: Track,
+    pub source: Source,
+}
+
+#[instrument(skip(rules_map))]
+fn recommend_songs(query: Vec<String>, rules_map: &RulesMap) -> Vec<(String, Source)> {
+    let songs = recommend(query, &rules_map.rules, &rules_map.popular_items);
+    debug!(n_songs = songs.len(), "Sending response.");
+    songs
 }
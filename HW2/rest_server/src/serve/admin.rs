@@ -0,0 +1,215 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Mutex,
+};
+
+use ml_processor::{
+    check_http_url, dataset_file_name, run_with_status, Columns, State, Status, StatusReport,
+};
+use tokio::task::spawn_blocking;
+
+use self::read_rules::ReloadOutcome;
+
+use auth::{require_scope, Auth, Scope};
+
+use super::*;
+
+/// Routes for operators, requiring the `admin` scope.
+/// No mining job starts once `ready` is false.
+pub fn router(
+    config: Arc<Config>,
+    auth: Arc<Auth>,
+    health: Arc<Health>,
+    rule_server_ref: Ref<RuleServer>,
+    ready: Arc<AtomicBool>,
+) -> Router {
+    let jobs = Arc::new(MineJobs::default());
+    let (mine_config, mine_jobs) = (config.clone(), jobs.clone());
+    Router::new()
+        .route("/config", get(|| async move { config_handler(&config) }))
+        .route("/status", get(|| async move { Json(health.status()) }))
+        .route(
+            "/reload",
+            post(|| async move { reload_handler(rule_server_ref.clone()).await }),
+        )
+        .route(
+            "/mine",
+            post(|request| async move { mine_handler(request, &mine_config, &mine_jobs, &ready) })
+                .get(|| async move { mine_status_handler(&jobs) }),
+        )
+        .route_layer(from_fn(move |request: Request, next: Next| {
+            require_scope(auth.clone(), Scope::Admin, request, next)
+        }))
+}
+
+/// The effective configuration, read-only.
+fn config_handler(config: &Config) -> Json<Config> {
+    Json(config.clone())
+}
+
+/// Reread the model from the data directory regardless of the checkpoint,
+/// answering once it is read.
+async fn reload_handler(
+    mut rule_server_ref: Ref<RuleServer>,
+) -> Result<Json<ReloadOutcome>, AppError> {
+    info!("Forced reload requested.");
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    rule_server_ref
+        .cast(RuleServerMsg::Reload(reply_sender))
+        .await?;
+    let outcome = reply_receiver.await.context("Rule server stopped")??;
+    info!(?outcome, "Forced reload done.");
+    Ok(Json(outcome))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MineRequest {
+    pub dataset_url: String,
+    /// Defaults to the ML processor's, [`Columns::default`].
+    pub transaction_column: Option<String>,
+    /// Comma-separated, defaults to the ML processor's.
+    pub item_columns: Option<String>,
+}
+
+/// The latest mining job, run in-process one at a time.
+#[derive(Debug, Default)]
+struct MineJobs {
+    next_id: AtomicU64,
+    latest: Mutex<Option<MineJob>>,
+}
+
+impl Drop for MineJobs {
+    /// The job cannot be interrupted, so the server stops without it.
+    fn drop(&mut self) {
+        let latest = self.latest.get_mut().expect("Jobs lock poisoned");
+        if let Some(job) = latest.as_ref().filter(|job| !job.task.is_finished()) {
+            warn!(job.id, "Abandoning the running mining job.");
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MineJob {
+    id: u64,
+    status: Status,
+    outcome: Arc<Mutex<JobOutcome>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MineJobReport {
+    pub id: u64,
+    pub outcome: JobOutcome,
+    /// Progress of the ML processor, with the error if it failed.
+    pub progress: StatusReport,
+}
+
+impl MineJob {
+    fn outcome(&self) -> JobOutcome {
+        match *self.outcome.lock().expect("Job lock poisoned") {
+            // The job panicked.
+            JobOutcome::Running if self.task.is_finished() => JobOutcome::Failed,
+            outcome => outcome,
+        }
+    }
+
+    fn report(&self) -> MineJobReport {
+        MineJobReport {
+            id: self.id,
+            outcome: self.outcome(),
+            progress: self.status.report(),
+        }
+    }
+}
+
+/// Start mining `dataset_url` into the data directory, which the file watcher
+/// then picks up; poll `GET /admin/mine` for progress.
+fn mine_handler(
+    Json(request): Json<MineRequest>,
+    config: &Config,
+    jobs: &MineJobs,
+    ready: &AtomicBool,
+) -> Result<(StatusCode, Json<MineJobReport>), AppError> {
+    if !ready.load(Ordering::Relaxed) {
+        return Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow!("The server is shutting down."),
+        ));
+    }
+    check_http_url(&request.dataset_url)
+        .and_then(|()| dataset_file_name(&request.dataset_url))
+        .map_err(|why| AppError::new(StatusCode::BAD_REQUEST, why))?;
+    let default = Columns::default();
+    let columns = Columns::new(
+        request
+            .transaction_column
+            .as_deref()
+            .unwrap_or(&default.transaction),
+        &request
+            .item_columns
+            .unwrap_or_else(|| default.items.join(",")),
+    )
+    .map_err(|why| AppError::new(StatusCode::BAD_REQUEST, why))?;
+
+    let mut latest = jobs.latest.lock().expect("Jobs lock poisoned");
+    if let Some(job) = latest.as_ref() {
+        if job.outcome() == JobOutcome::Running {
+            return Err(AppError::new(
+                StatusCode::CONFLICT,
+                anyhow!("Mining job {} is still running.", job.id),
+            ));
+        }
+    }
+
+    let id = jobs.next_id.fetch_add(1, Ordering::Relaxed);
+    info!(id, request.dataset_url, ?columns, "Starting mining job.");
+    let status = Status::default();
+    status.start_run(&request.dataset_url);
+    status.set_state(State::Downloading);
+    let outcome = Arc::new(Mutex::new(JobOutcome::Running));
+
+    let task = {
+        let (status, outcome, data_dir) =
+            (status.clone(), outcome.clone(), config.data_dir.clone());
+        spawn_blocking(move || {
+            let result = run_with_status(&request.dataset_url, data_dir, &columns, &status);
+            match &result {
+                Ok(()) => info!(id, "Mining job succeeded."),
+                Err(why) => error!(id, why = why.chain(), "Mining job failed."),
+            }
+            status.finish_run(&result);
+            *outcome.lock().expect("Job lock poisoned") = match result {
+                Ok(()) => JobOutcome::Succeeded,
+                Err(_) => JobOutcome::Failed,
+            };
+        })
+    };
+
+    let job = MineJob {
+        id,
+        status,
+        outcome,
+        task,
+    };
+    let report = job.report();
+    *latest = Some(job);
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+fn mine_status_handler(jobs: &MineJobs) -> Result<Json<MineJobReport>, AppError> {
+    match jobs.latest.lock().expect("Jobs lock poisoned").as_ref() {
+        Some(job) => Ok(Json(job.report())),
+        None => Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("No mining job has run."),
+        )),
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fmt, fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::Request,
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::*;
+
+use limit::{client_ip, RateLimiter, AUTH_FAILURES_PER_SEC, AUTH_FAILURE_BURST};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header for API keys, as an alternative to `Authorization: Bearer <key>`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+const MIN_KEY_LEN: usize = 16;
+const MIN_SECRET_LEN: usize = 32;
+
+/// What a credential grants access to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// `/api/recommend` and `/api/songs`.
+    Recommend,
+    /// The `/admin` routes.
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Recommend => "recommend",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+/// The `auth_file`, in TOML.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthFile {
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+    /// Secrets for HMAC-SHA256-signed bearer tokens,
+    /// several while rotating.
+    #[serde(default)]
+    pub hmac_secrets: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    /// Who uses the key, for logs.
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Payload of a signed bearer token,
+/// `<base64url payload JSON>.<base64url HMAC-SHA256 of the first part>`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenClaims {
+    pub sub: String,
+    pub scopes: Vec<Scope>,
+    /// Seconds since UNIX epoch after which the token is rejected.
+    pub exp: Option<u64>,
+}
+
+/// Who made a request, added to the request extensions once authenticated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Credentials accepted by the server.
+#[derive(Debug)]
+pub struct Auth {
+    keys: Vec<ApiKey>,
+    hmac_secrets: Vec<String>,
+    /// Whether the `/api` routes require credentials, i.e., an auth file is
+    /// configured; the `/admin` routes always do.
+    protects_api: bool,
+    /// Rejected credentials per IP address, against guessing.
+    failures: RateLimiter,
+}
+
+impl Auth {
+    /// Read the `auth_file` if configured, and accept the `admin_token`.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let (auth_file, protects_api) = match &config.auth_file {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read auth file {path:?}"))?;
+                let auth_file = toml::from_str(&content)
+                    .with_context(|| format!("Invalid auth file {path:?}"))?;
+                (auth_file, true)
+            }
+            None => (AuthFile::default(), false),
+        };
+        let mut auth = Self::new(auth_file, protects_api)?;
+        if let Some(token) = &config.admin_token {
+            auth.keys.push(ApiKey {
+                name: "admin_token".into(),
+                key: token.clone(),
+                scopes: vec![Scope::Admin],
+            });
+        }
+        Ok(auth)
+    }
+
+    pub fn new(auth_file: AuthFile, protects_api: bool) -> Result<Self> {
+        let AuthFile { keys, hmac_secrets } = auth_file;
+        for key in &keys {
+            if key.key.len() < MIN_KEY_LEN {
+                bail!(
+                    "API key `{}` has fewer than {MIN_KEY_LEN} characters.",
+                    key.name
+                );
+            }
+            if key.scopes.is_empty() {
+                bail!("API key `{}` has no scopes.", key.name);
+            }
+        }
+        if hmac_secrets
+            .iter()
+            .any(|secret| secret.len() < MIN_SECRET_LEN)
+        {
+            bail!("An HMAC secret has fewer than {MIN_SECRET_LEN} characters.");
+        }
+        Ok(Self {
+            keys,
+            hmac_secrets,
+            protects_api,
+            failures: RateLimiter::new(AUTH_FAILURES_PER_SEC, AUTH_FAILURE_BURST),
+        })
+    }
+
+    /// Whether `scope` is closed to requests without credentials.
+    pub fn requires_credentials(&self, scope: Scope) -> bool {
+        scope != Scope::Recommend || self.protects_api
+    }
+
+    /// Check the credentials in `headers` for `scope`,
+    /// giving no principal if the scope is open to anyone.
+    pub fn authorize(
+        &self,
+        headers: &HeaderMap,
+        scope: Scope,
+    ) -> Result<Option<Principal>, AuthError> {
+        if !self.requires_credentials(scope) {
+            return Ok(None);
+        }
+        if self.keys.is_empty() && self.hmac_secrets.is_empty() {
+            return Err(AuthError::Disabled);
+        }
+        let credential = credential(headers).ok_or(AuthError::Missing)?;
+        let principal = self
+            .principal_for_key(credential)
+            .map(Ok)
+            .unwrap_or_else(|| self.verify_token(credential))?;
+        match principal.scopes.contains(&scope) {
+            true => Ok(Some(principal)),
+            false => Err(AuthError::Forbidden {
+                name: principal.name,
+                scope,
+            }),
+        }
+    }
+
+    fn principal_for_key(&self, credential: &str) -> Option<Principal> {
+        // Compare with every key so that the time taken does not reveal which.
+        self.keys
+            .iter()
+            .fold(None, |found, key| {
+                match constant_time_eq(key.key.as_bytes(), credential.as_bytes()) {
+                    true => Some(key),
+                    false => found,
+                }
+            })
+            .map(|key| Principal {
+                name: key.name.clone(),
+                scopes: key.scopes.clone(),
+            })
+    }
+
+    fn verify_token(&self, token: &str) -> Result<Principal, AuthError> {
+        let (payload, signature) = token.split_once('.').ok_or(AuthError::Invalid)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::Invalid)?;
+        let signed = self.hmac_secrets.iter().any(|secret| {
+            let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(payload.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        });
+        if !signed {
+            return Err(AuthError::Invalid);
+        }
+        let claims: TokenClaims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(AuthError::Invalid)?;
+        if let Some(exp) = claims.exp {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Current time is later than UNIX epoch")
+                .as_secs();
+            if now > exp {
+                return Err(AuthError::Expired { name: claims.sub });
+            }
+        }
+        Ok(Principal {
+            name: claims.sub,
+            scopes: claims.scopes,
+        })
+    }
+}
+
+/// Sign `claims` into a bearer token with `secret`.
+#[cfg(test)]
+pub fn sign_token(claims: &TokenClaims, secret: &str) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("Claims serialize"));
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    format!("{payload}.{signature}")
+}
+
+/// The bearer token or API key in `headers`.
+fn credential(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = || {
+        headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+    };
+    bearer.or_else(api_key).map(str::trim)
+}
+
+/// Compare without exiting early, so that the time taken does not reveal how
+/// much of a guessed key is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// No credentials grant the scope at all.
+    Disabled,
+    Missing,
+    Invalid,
+    Expired {
+        name: String,
+    },
+    Forbidden {
+        name: String,
+        scope: Scope,
+    },
+}
+
+impl From<AuthError> for AppError {
+    fn from(value: AuthError) -> Self {
+        let (status, message) = match value {
+            AuthError::Disabled => (
+                StatusCode::FORBIDDEN,
+                "No credentials are configured for this route.".into(),
+            ),
+            AuthError::Missing => (StatusCode::UNAUTHORIZED, "Missing credentials.".into()),
+            AuthError::Invalid => (StatusCode::UNAUTHORIZED, "Invalid credentials.".into()),
+            AuthError::Expired { .. } => (StatusCode::UNAUTHORIZED, "Expired token.".into()),
+            AuthError::Forbidden { name, scope } => (
+                StatusCode::FORBIDDEN,
+                format!("`{name}` lacks the `{scope}` scope."),
+            ),
+        };
+        AppError::new(status, anyhow!(message))
+    }
+}
+
+/// Reject requests without credentials for `scope`,
+/// or pass them on with their [`Principal`] if any.
+/// Clients that presented too many rejected credentials for `scope`
+/// get 429 instead, except over a Unix socket, where clients have no address.
+pub async fn require_scope(
+    auth: Arc<Auth>,
+    scope: Scope,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let client = match auth.requires_credentials(scope) {
+        true => client_ip(request.extensions()).map(|ip| format!("{scope}:{ip}")),
+        false => None,
+    };
+    let now = Instant::now();
+    if let Some(client) = &client {
+        if let Err(retry_after) = auth.failures.peek(client, now) {
+            debug!(client, ?retry_after, "Too many rejected credentials.");
+            return Err(AppError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                anyhow!("Too many rejected credentials."),
+            )
+            .with_retry_after(retry_after));
+        }
+    }
+    match auth.authorize(request.headers(), scope) {
+        Ok(principal) => {
+            if let Some(principal) = principal {
+                request.extensions_mut().insert(principal);
+            }
+            Ok(next.run(request).await)
+        }
+        Err(why) => {
+            warn!(
+                path = request.uri().path(),
+                ?scope,
+                ?why,
+                "Rejected request."
+            );
+            if let Some(client) = &client {
+                _ = auth.failures.check(client, now);
+            }
+            Err(why.into())
+        }
+    }
+}
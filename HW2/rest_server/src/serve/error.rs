@@ -1,20 +1,57 @@
 //! Copied from
 //! <https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs>.
-use axum::response::{IntoResponse, Response};
+use axum::{
+    http::header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    response::{IntoResponse, Response},
+};
 
 use super::*;
 
 // Make our own error that wraps `anyhow::Error`.
-pub struct AppError(anyhow::Error);
+pub struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
+    /// Sent as `Retry-After`, in whole seconds rounded up.
+    retry_after: Option<Duration>,
+}
+
+impl AppError {
+    /// An error answered with `status` and the error message,
+    /// rather than a 500.
+    pub fn new(status: StatusCode, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+}
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let mut response = match self.status {
+            StatusCode::INTERNAL_SERVER_ERROR => {
+                (self.status, format!("Something went wrong: {}", self.error)).into_response()
+            }
+            StatusCode::UNAUTHORIZED => (
+                self.status,
+                [(WWW_AUTHENTICATE, "Bearer")],
+                self.error.to_string(),
+            )
+                .into_response(),
+            status => (status, self.error.to_string()).into_response(),
+        };
+        if let Some(retry_after) = self.retry_after {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(RETRY_AFTER, seconds.into());
+        }
+        response
     }
 }
 
@@ -25,6 +62,6 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err)
     }
 }
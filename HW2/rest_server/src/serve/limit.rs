@@ -0,0 +1,219 @@
+use std::{convert::Infallible, net::IpAddr, sync::Mutex};
+
+use axum::{
+    extract::{connect_info::Connected, ConnectInfo, FromRequestParts, Request},
+    http::{request::Parts, Extensions},
+    middleware::Next,
+    response::Response,
+    serve::IncomingStream,
+};
+use tokio::sync::Semaphore;
+
+use super::*;
+
+use auth::Principal;
+
+/// Buckets kept at most, evicting the least recently refilled beyond that.
+const MAX_BUCKETS: usize = 10_000;
+/// Rejected credentials each IP address may present at once.
+pub const AUTH_FAILURE_BURST: u32 = 10;
+/// Rate at which each IP address may present more rejected credentials.
+pub const AUTH_FAILURES_PER_SEC: f64 = 0.1;
+
+/// IP address of the client, unknown over a Unix socket.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub Option<IpAddr>);
+
+impl Connected<IncomingStream<'_, TcpListener>> for ClientAddr {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self(Some(stream.remote_addr().ip()))
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for ClientAddr {
+    fn connect_info(_stream: IncomingStream<'_, UnixListener>) -> Self {
+        Self(None)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiting per client:
+/// each client may make `burst` requests at once,
+/// refilled at `per_second`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst: burst.into(),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Take a token for `client` at `now`,
+    /// or return how long until one is available.
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, 1.0)
+    }
+
+    /// Like [`Self::check`], but leave the token.
+    pub fn peek(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, 0.0)
+    }
+
+    /// Take `tokens` from `client` at `now` however few are left,
+    /// so that its later requests wait until they are refilled.
+    pub fn charge(&self, client: &str, now: Instant, tokens: f64) {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        self.refill(&mut buckets, client, now).tokens -= tokens;
+    }
+
+    fn take(&self, client: &str, now: Instant, cost: f64) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        let bucket = self.refill(&mut buckets, client, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    /// The bucket of `client`, refilled up to `now`.
+    fn refill<'a>(
+        &self,
+        buckets: &'a mut HashMap<String, TokenBucket>,
+        client: &str,
+        now: Instant,
+    ) -> &'a mut TokenBucket {
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(client) {
+            evict_least_recent(buckets);
+        }
+        let bucket = buckets.entry(client.into()).or_insert(TokenBucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        bucket.last_refill = now;
+        bucket
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.buckets
+            .lock()
+            .expect("Rate limiter lock poisoned")
+            .len()
+    }
+}
+
+/// Drop the bucket refilled longest ago, most likely full again,
+/// so that many clients cannot grow the map without bound.
+fn evict_least_recent(buckets: &mut HashMap<String, TokenBucket>) {
+    let oldest = buckets
+        .iter()
+        .min_by_key(|(_, bucket)| bucket.last_refill)
+        .map(|(client, _)| client.clone());
+    if let Some(oldest) = oldest {
+        buckets.remove(&oldest);
+    }
+}
+
+/// Limits on the `/api` routes.
+#[derive(Debug)]
+pub struct Limits {
+    /// `None` if rate limiting is disabled.
+    rate_limiter: Option<RateLimiter>,
+    concurrency: Arc<Semaphore>,
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        let rate_limiter = (config.rate_limit_per_sec > 0.0)
+            .then(|| RateLimiter::new(config.rate_limit_per_sec, config.rate_limit_burst));
+        Self {
+            rate_limiter,
+            concurrency: Arc::new(Semaphore::new(config.max_concurrent_requests)),
+        }
+    }
+
+    /// Take `requests` more rate-limit tokens from `client`,
+    /// e.g., for the rest of a batch.
+    pub fn charge(&self, client: &Client, requests: usize) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.charge(&client.0, Instant::now(), requests as f64);
+        }
+    }
+}
+
+/// Who the rate limit applies to, as a handler argument.
+#[derive(Clone, Debug)]
+pub struct Client(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(Self(client_key(&parts.extensions)))
+    }
+}
+
+/// Who the rate limit applies to: the authenticated principal,
+/// or else the client IP address.
+fn client_key(extensions: &Extensions) -> String {
+    match extensions.get::<Principal>() {
+        Some(principal) => format!("key:{}", principal.name),
+        None => client_ip(extensions).unwrap_or_else(|| "unknown".into()),
+    }
+}
+
+/// The client IP address, if not over a Unix socket.
+pub fn client_ip(extensions: &Extensions) -> Option<String> {
+    match extensions.get::<ConnectInfo<ClientAddr>>() {
+        Some(ConnectInfo(ClientAddr(Some(ip)))) => Some(format!("ip:{ip}")),
+        _ => None,
+    }
+}
+
+/// Reject requests over the client's rate limit or the concurrency limit
+/// with 429 and `Retry-After`.
+pub async fn enforce_limits(
+    limits: Arc<Limits>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(rate_limiter) = &limits.rate_limiter {
+        let client = client_key(request.extensions());
+        if let Err(retry_after) = rate_limiter.check(&client, Instant::now()) {
+            debug!(client, ?retry_after, "Rate limited.");
+            return Err(AppError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                anyhow!("Rate limit exceeded."),
+            )
+            .with_retry_after(retry_after));
+        }
+    }
+    let Ok(_permit) = limits.concurrency.clone().try_acquire_owned() else {
+        debug!("Concurrency limit reached.");
+        return Err(AppError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            anyhow!("Too many concurrent requests."),
+        )
+        .with_retry_after(ONE_SECOND));
+    };
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,1121 @@
+use std::{env::temp_dir, fs};
+
+use super::*;
+
+fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    |name| {
+        vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+#[test]
+fn config_env_overrides_file() {
+    let path = temp_dir().join(format!("rest_server-config-{}.toml", std::process::id()));
+    fs::write(
+        &path,
+        "bind = \"[::]:8080\"\nmax_search_limit = 50\ncors_origins = [\"https://a.example\"]\n",
+    )
+    .unwrap();
+    let config = Config::load(
+        Some(&path),
+        env(&[
+            ("PORT", "9090"),
+            ("LOG_FORMAT", "json"),
+            ("WATCH_BACKEND", "poll"),
+        ]),
+    );
+    fs::remove_file(&path).unwrap();
+
+    let config = config.unwrap();
+    assert_eq!(config.bind, "[::]:9090".parse().unwrap());
+    assert_eq!(config.max_search_limit, 50);
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.watch_backend, WatchBackend::Poll);
+    assert_eq!(config.cors_origins, ["https://a.example"]);
+    assert_eq!(config.data_dir, Config::default().data_dir);
+}
+
+#[test]
+fn config_rejects_invalid_values() {
+    let load = |vars: &[(&str, &str)]| Config::load(None, env(vars));
+    assert!(load(&[]).is_ok());
+    assert!(load(&[("DEFAULT_SEARCH_LIMIT", "200")]).is_err());
+    assert!(load(&[("MAX_BODY_BYTES", "0")]).is_err());
+    assert!(load(&[("WATCH_BACKEND", "fanotify")]).is_err());
+    assert!(load(&[("CORS_ORIGINS", "example.com")]).is_err());
+    assert!(load(&[("BIND_ADDRESS", "unix:/tmp/s.sock"), ("PORT", "80")]).is_err());
+    assert_eq!(
+        load(&[("BIND_ADDRESS", "unix:/tmp/s.sock")]).unwrap().bind,
+        BindAddress::Unix("/tmp/s.sock".into())
+    );
+}
+
+mod catalog {
+    use super::*;
+    use crate::catalog::{normalize_name, Catalog, MatchKind};
+
+    fn catalog() -> Catalog {
+        let song = |name: &str, count| Song {
+            name: name.into(),
+            artist: None,
+            count,
+        };
+        let songs = SongCatalog::from([
+            ("spotify:track:1".into(), song("Bohemian Rhapsody", 10)),
+            ("spotify:track:2".into(), song("HUMBLE.", 50)),
+            ("spotify:track:3".into(), song("Humble", 5)),
+            ("spotify:track:4".into(), song("Abcdefghij", 1)),
+            ("spotify:track:5".into(), song("Abcdefghik", 1)),
+        ]);
+        Catalog::new(songs, &RuleIndex::new())
+    }
+
+    fn resolve(query: &str) -> (Option<String>, MatchKind) {
+        let resolution = catalog().resolve(query);
+        (resolution.track.map(|track| track.id), resolution.kind)
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_name("  Bohemian   RHAPSODY! "),
+            "bohemian rhapsody"
+        );
+        assert_eq!(normalize_name("ＨＵＭＢＬＥ."), "humble");
+        assert_eq!(normalize_name("Straße"), "strasse");
+    }
+
+    #[test]
+    fn resolve_exact_normalized_fuzzy_and_unmatched() {
+        let id = |n: u8| Some(format!("spotify:track:{n}"));
+        assert_eq!(resolve("spotify:track:1"), (id(1), MatchKind::Exact));
+        assert_eq!(resolve("Bohemian Rhapsody"), (id(1), MatchKind::Exact));
+        assert_eq!(
+            resolve("bohemian rhapsody "),
+            (id(1), MatchKind::Normalized)
+        );
+        // Both normalize to `humble`, so the more frequent one wins.
+        assert_eq!(resolve("humble"), (id(2), MatchKind::Normalized));
+        assert_eq!(resolve("Bohemian Rapsody"), (id(1), MatchKind::Fuzzy));
+        assert_eq!(resolve("Stairway to Heaven"), (None, MatchKind::Unmatched));
+    }
+
+    #[test]
+    fn fuzzy_ties_resolve_alphabetically() {
+        // Equally similar to `abcdefghij` and `abcdefghik`.
+        for _ in 0..10 {
+            assert_eq!(
+                resolve("Abcdefghil"),
+                (Some("spotify:track:4".into()), MatchKind::Fuzzy)
+            );
+        }
+    }
+}
+
+mod search {
+    use super::*;
+    use crate::{catalog::Catalog, search::SongIndex};
+
+    fn index() -> SongIndex {
+        let song = |name: &str, count| Song {
+            name: name.into(),
+            artist: None,
+            count,
+        };
+        let songs = SongCatalog::from([
+            ("spotify:track:1".into(), song("Hello", 10)),
+            ("spotify:track:2".into(), song("Help!", 30)),
+            ("spotify:track:3".into(), song("Hey Jude", 20)),
+            ("spotify:track:4".into(), song("HELLO", 10)),
+            ("spotify:track:5".into(), song("Yesterday", 40)),
+        ]);
+        let rules = RuleIndex::from([
+            (
+                vec!["spotify:track:1".into(), "spotify:track:2".into()],
+                ["spotify:track:3".into()].into(),
+            ),
+            (
+                vec!["spotify:track:4".into()],
+                ["spotify:track:5".into(), "Unknown Song".into()].into(),
+            ),
+        ]);
+        SongIndex::new(&rules, &Catalog::new(songs, &rules))
+    }
+
+    fn names(prefix: &str, limit: usize) -> Vec<String> {
+        index()
+            .search(prefix, limit)
+            .into_iter()
+            .map(|entry| entry.track.name)
+            .collect()
+    }
+
+    #[test]
+    fn prefix_matches_most_frequent_first() {
+        assert_eq!(names("he", 10), ["Help!", "Hey Jude", "HELLO", "Hello"]);
+        assert_eq!(names("  HEL", 10), ["Help!", "HELLO", "Hello"]);
+        assert_eq!(names("hello", 10), ["HELLO", "Hello"]);
+        assert_eq!(names("unknown", 10), ["Unknown Song"]);
+        assert!(names("z", 10).is_empty());
+        assert_eq!(names("", 10).len(), 6);
+    }
+
+    #[test]
+    fn limit_keeps_the_most_frequent() {
+        assert_eq!(names("he", 2), ["Help!", "Hey Jude"]);
+        assert_eq!(names("", 1), ["Yesterday"]);
+        assert!(names("he", 0).is_empty());
+    }
+}
+
+mod watch {
+    use notify::{
+        event::{AccessKind, AccessMode, CreateKind, DataChange, MetadataKind, ModifyKind},
+        Event, EventKind,
+    };
+
+    use tokio::{sync::mpsc, time::Instant};
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        read_rules::RuleServerMsg,
+        watch_file::{is_model_write, Debounce, FileWatcher, Settled, WatchBackend, WatchOptions},
+    };
+
+    const WINDOW: Duration = Duration::from_millis(500);
+
+    fn file_names() -> Vec<std::ffi::OsString> {
+        [checkpoint_path("ml-data"), rules_path("ml-data")]
+            .iter()
+            .map(|path| path.file_name().unwrap().into())
+            .collect()
+    }
+
+    fn event(kind: EventKind, file: PathBuf) -> Event {
+        Event::new(kind).add_path(file)
+    }
+
+    /// The events of one ML Processor run writing the model.
+    fn model_write_burst() -> Vec<Event> {
+        let data_dir = Path::new("/data/ml-data");
+        let write = EventKind::Modify(ModifyKind::Data(DataChange::Any));
+        vec![
+            event(
+                EventKind::Create(CreateKind::File),
+                frequencies_path(data_dir),
+            ),
+            event(write, frequencies_path(data_dir)),
+            event(EventKind::Create(CreateKind::File), rules_path(data_dir)),
+            event(write, rules_path(data_dir)),
+            event(write, rules_path(data_dir)),
+            event(
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                rules_path(data_dir),
+            ),
+            event(EventKind::Create(CreateKind::File), metadata_path(data_dir)),
+            event(
+                EventKind::Create(CreateKind::File),
+                checkpoint_path(data_dir),
+            ),
+            event(write, checkpoint_path(data_dir)),
+            event(
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+                checkpoint_path(data_dir),
+            ),
+        ]
+    }
+
+    /// Expire the timer up to `now` as the timer task would,
+    /// returning the number of notifications fired.
+    fn expire(debounce: &mut Debounce, timer: &mut Option<Instant>, now: Instant) -> usize {
+        let mut fires = 0;
+        while let Some(deadline) = timer.filter(|deadline| *deadline <= now) {
+            *timer = match debounce.settle(deadline) {
+                Settled::Fire => {
+                    fires += 1;
+                    None
+                }
+                Settled::WaitUntil(deadline) => Some(deadline),
+                Settled::Idle => None,
+            };
+        }
+        fires
+    }
+
+    /// Feed each burst's events to a debouncer one per its interval,
+    /// pausing for `pause` after each burst,
+    /// and count the notifications fired.
+    fn count_fires(bursts: &[(Vec<Event>, Duration)], pause: Duration) -> usize {
+        let file_names = file_names();
+        let mut debounce = Debounce::new(WINDOW);
+        let mut now = Instant::now();
+        let mut timer = None;
+        let mut fires = 0;
+        for (events, interval) in bursts {
+            for event in events {
+                now += *interval;
+                fires += expire(&mut debounce, &mut timer, now);
+                if is_model_write(event, &file_names) && debounce.event(now) {
+                    timer = Some(now + WINDOW);
+                }
+            }
+            now += pause;
+            fires += expire(&mut debounce, &mut timer, now);
+        }
+        fires
+    }
+
+    #[test]
+    fn filters_event_kinds_and_paths() {
+        let file_names = file_names();
+        let relevant: Vec<bool> = model_write_burst()
+            .iter()
+            .map(|event| is_model_write(event, &file_names))
+            .collect();
+        assert_eq!(
+            relevant,
+            [false, false, true, true, true, true, false, true, true, false]
+        );
+        let removal = event(
+            EventKind::Remove(notify::event::RemoveKind::File),
+            checkpoint_path("ml-data"),
+        );
+        assert!(!is_model_write(&removal, &file_names));
+    }
+
+    #[test]
+    fn burst_of_writes_fires_once() {
+        let burst = model_write_burst();
+        assert_eq!(
+            count_fires(&[(burst, Duration::from_millis(20))], WINDOW),
+            1
+        );
+    }
+
+    #[test]
+    fn slow_burst_within_window_fires_once() {
+        // Each write extends the window, so the burst may outlast it.
+        let file_names = file_names();
+        let burst = model_write_burst()
+            .into_iter()
+            .filter(|event| is_model_write(event, &file_names))
+            .collect();
+        let fires = count_fires(&[(burst, WINDOW - Duration::from_millis(1))], WINDOW);
+        assert_eq!(fires, 1);
+    }
+
+    #[test]
+    fn separate_bursts_fire_separately() {
+        let bursts = [
+            (model_write_burst(), Duration::from_millis(10)),
+            (model_write_burst(), Duration::from_millis(10)),
+            (model_write_burst(), Duration::from_millis(10)),
+        ];
+        assert_eq!(count_fires(&bursts, WINDOW * 2), 3);
+    }
+
+    #[test]
+    fn settle_before_deadline_waits() {
+        let start = Instant::now();
+        let mut debounce = Debounce::new(WINDOW);
+        assert!(debounce.event(start));
+        assert!(!debounce.event(start + WINDOW / 2));
+        assert_eq!(
+            debounce.settle(start + WINDOW),
+            Settled::WaitUntil(start + WINDOW / 2 + WINDOW)
+        );
+        assert_eq!(debounce.settle(start + WINDOW * 2), Settled::Fire);
+        assert_eq!(debounce.settle(start + WINDOW * 3), Settled::Idle);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn watcher_notifies_once_per_settled_burst() {
+        let data_dir = temp_dir().join(format!("rest_server-watch-{}", std::process::id()));
+        fs::create_dir_all(&data_dir).unwrap();
+        let (msg_sender, mut receiver) = mpsc::channel(8);
+        let server_ref = Ref::<RuleServer> {
+            msg_sender,
+            cancellation_token: CancellationToken::new(),
+        };
+        let options = WatchOptions {
+            backend: WatchBackend::Poll,
+            debounce: WINDOW,
+            poll_interval: Duration::from_secs(1),
+        };
+        let health = Arc::new(Health::new(Config::default().retry_policy()));
+        let (_, watcher_ref) =
+            FileWatcher::new(data_dir.clone(), options, health, server_ref).spawn();
+
+        let mut notified = 0;
+        let mut count_notifications = || {
+            while let Ok(msg) = receiver.try_recv() {
+                if let Msg::Cast(RuleServerMsg::WatchedFileChanged(_)) = msg {
+                    notified += 1;
+                }
+            }
+            notified
+        };
+        let checkpoint = checkpoint_path(&data_dir);
+
+        sleep(Duration::from_millis(2500)).await;
+        assert_eq!(count_notifications(), 0);
+
+        fs::write(&checkpoint, "0.1.2 url 1 pid track_uri").unwrap();
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(count_notifications(), 1);
+
+        // Rewrites between two polls are one change.
+        for n in 2..5 {
+            fs::write(&checkpoint, format!("0.1.2 url {n} pid track_uri")).unwrap();
+        }
+        sleep(Duration::from_secs(3)).await;
+        assert_eq!(count_notifications(), 2);
+
+        watcher_ref.cancellation_token.cancel();
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
+
+mod poll {
+    use std::{env::temp_dir, fs};
+
+    use crate::watch_file::{FileStamp, PollOutcome, Poller};
+
+    #[test]
+    fn stamp_changes_with_same_size_rewrite() {
+        let path = temp_dir().join(format!("rest_server-stamp-{}.txt", std::process::id()));
+        fs::write(&path, "0.1.2 url 1 pid tid").unwrap();
+        let before = FileStamp::read(&path).unwrap();
+        fs::write(&path, "0.1.2 url 2 pid tid").unwrap();
+        let after = FileStamp::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_ne!(before, after);
+    }
+
+    fn stamp(content: &str) -> Option<FileStamp> {
+        let path = temp_dir().join(format!("rest_server-poll-{}.txt", std::process::id()));
+        fs::write(&path, content).unwrap();
+        let stamp = FileStamp::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        Some(stamp)
+    }
+
+    #[test]
+    fn poller_detects_silent_native_watcher() {
+        let (first, second, third) = (stamp("1"), stamp("2"), stamp("3"));
+        let mut poller = Poller::new(None);
+        assert_eq!(poller.poll(None, true), PollOutcome::Unchanged);
+
+        poller.native_event();
+        assert_eq!(poller.poll(first.clone(), true), PollOutcome::Unchanged);
+        assert_eq!(poller.poll(first, true), PollOutcome::Unchanged);
+        assert_eq!(poller.poll(second.clone(), true), PollOutcome::Unchanged);
+        assert_eq!(poller.poll(second, true), PollOutcome::NativeSilent);
+        assert_eq!(poller.poll(third.clone(), false), PollOutcome::Changed);
+        assert_eq!(poller.poll(third, false), PollOutcome::Unchanged);
+    }
+
+    #[test]
+    fn poller_tolerates_native_event_after_poll() {
+        let (first, second) = (stamp("1"), stamp("2"));
+        let mut poller = Poller::new(first);
+        // The poll races the write, seeing it before its native event.
+        assert_eq!(poller.poll(second.clone(), true), PollOutcome::Unchanged);
+        poller.native_event();
+        assert_eq!(poller.poll(second.clone(), true), PollOutcome::Unchanged);
+        assert_eq!(poller.poll(second, true), PollOutcome::Unchanged);
+    }
+}
+
+mod retry {
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::retry::{Health, RetryPolicy};
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+        jitter: 0.0,
+        degraded_after: 3,
+    };
+
+    #[test]
+    fn delay_doubles_up_to_cap() {
+        let delays: Vec<u64> = (1..=6)
+            .map(|failures| POLICY.delay(failures, 0.5).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(POLICY.delay(u32::MAX, 0.0), POLICY.max);
+    }
+
+    #[test]
+    fn jitter_shortens_delay_within_bounds() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..POLICY
+        };
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(4));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_secs(3));
+        assert!(policy.delay(3, 0.999) > Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_waits_and_degrades() {
+        let health = Health::new(POLICY);
+        let error = anyhow!("No checkpoint");
+        let start = Instant::now();
+        for _ in 0..5 {
+            health.backoff("reload", &error).await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2 + 4 + 8 + 10));
+
+        let status = health.status();
+        assert_eq!(status.status, "degraded");
+        assert_eq!(status.tasks["reload"].consecutive_failures, 5);
+        assert_eq!(
+            status.tasks["reload"].last_error.as_deref(),
+            Some("No checkpoint")
+        );
+
+        health.succeeded("reload");
+        assert_eq!(health.status().status, "ok");
+        let start = Instant::now();
+        health.backoff("reload", &error).await;
+        assert_eq!(start.elapsed(), POLICY.initial);
+    }
+}
+
+mod auth {
+    use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+
+    use super::*;
+    use crate::serve::auth::{
+        sign_token, ApiKey, Auth, AuthError, AuthFile, Principal, Scope, TokenClaims,
+    };
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn auth() -> Auth {
+        let keys = vec![
+            ApiKey {
+                name: "playlist-job".into(),
+                key: "recommend-key-0123".into(),
+                scopes: vec![Scope::Recommend],
+            },
+            ApiKey {
+                name: "operator".into(),
+                key: "admin-key-0123456".into(),
+                scopes: vec![Scope::Recommend, Scope::Admin],
+            },
+        ];
+        let auth_file = AuthFile {
+            keys,
+            hmac_secrets: vec![SECRET.into()],
+        };
+        Auth::new(auth_file, true).unwrap()
+    }
+
+    fn bearer(credential: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = HeaderValue::from_str(&format!("Bearer {credential}")).unwrap();
+        headers.insert(AUTHORIZATION, value);
+        headers
+    }
+
+    #[test]
+    fn api_keys_have_scopes() {
+        let auth = auth();
+        let principal = auth
+            .authorize(&bearer("recommend-key-0123"), Scope::Recommend)
+            .unwrap()
+            .unwrap();
+        assert_eq!(principal.name, "playlist-job");
+        assert_eq!(
+            auth.authorize(&bearer("recommend-key-0123"), Scope::Admin),
+            Err(AuthError::Forbidden {
+                name: "playlist-job".into(),
+                scope: Scope::Admin
+            })
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("admin-key-0123456"));
+        assert!(auth.authorize(&headers, Scope::Admin).is_ok());
+
+        assert_eq!(
+            auth.authorize(&HeaderMap::new(), Scope::Recommend),
+            Err(AuthError::Missing)
+        );
+        assert_eq!(
+            auth.authorize(&bearer("admin-key-0123457"), Scope::Recommend),
+            Err(AuthError::Invalid)
+        );
+    }
+
+    #[test]
+    fn signed_tokens_are_verified() {
+        let auth = auth();
+        let claims = TokenClaims {
+            sub: "dashboard".into(),
+            scopes: vec![Scope::Recommend],
+            exp: None,
+        };
+        let token = sign_token(&claims, SECRET);
+        assert_eq!(
+            auth.authorize(&bearer(&token), Scope::Recommend),
+            Ok(Some(Principal {
+                name: "dashboard".into(),
+                scopes: vec![Scope::Recommend]
+            }))
+        );
+
+        let forged = sign_token(&claims, "another-secret-0123456789abcdef0");
+        assert_eq!(
+            auth.authorize(&bearer(&forged), Scope::Recommend),
+            Err(AuthError::Invalid)
+        );
+        let (_, signature) = token.split_once('.').unwrap();
+        let escalated = TokenClaims {
+            scopes: vec![Scope::Admin],
+            ..claims.clone()
+        };
+        let escalated = sign_token(&escalated, SECRET);
+        let (payload, _) = escalated.split_once('.').unwrap();
+        assert_eq!(
+            auth.authorize(&bearer(&format!("{payload}.{signature}")), Scope::Admin),
+            Err(AuthError::Invalid)
+        );
+
+        let expired = sign_token(
+            &TokenClaims {
+                exp: Some(1),
+                ..claims
+            },
+            SECRET,
+        );
+        assert_eq!(
+            auth.authorize(&bearer(&expired), Scope::Recommend),
+            Err(AuthError::Expired {
+                name: "dashboard".into()
+            })
+        );
+    }
+
+    #[test]
+    fn without_auth_file_only_admin_needs_credentials() {
+        let auth = Auth::new(AuthFile::default(), false).unwrap();
+        assert_eq!(
+            auth.authorize(&HeaderMap::new(), Scope::Recommend),
+            Ok(None)
+        );
+        assert_eq!(
+            auth.authorize(&bearer("admin-key-0123456"), Scope::Admin),
+            Err(AuthError::Disabled)
+        );
+
+        let config = Config {
+            admin_token: Some("admin-token-0123456".into()),
+            ..Config::default()
+        };
+        let auth = Auth::from_config(&config).unwrap();
+        assert!(auth.authorize(&HeaderMap::new(), Scope::Recommend).is_ok());
+        assert!(auth
+            .authorize(&bearer("admin-token-0123456"), Scope::Admin)
+            .is_ok());
+    }
+
+    #[test]
+    fn weak_keys_are_rejected() {
+        let auth_file = AuthFile {
+            keys: vec![ApiKey {
+                name: "short".into(),
+                key: "short".into(),
+                scopes: vec![Scope::Recommend],
+            }],
+            hmac_secrets: Vec::new(),
+        };
+        assert!(Auth::new(auth_file, true).is_err());
+    }
+}
+
+mod limit {
+    use super::*;
+    use crate::serve::limit::RateLimiter;
+
+    #[test]
+    fn burst_then_refill() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", start), Ok(()));
+        }
+        assert_eq!(limiter.check("a", start), Err(Duration::from_millis(500)));
+        // Other clients have their own bucket.
+        assert_eq!(limiter.check("b", start), Ok(()));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check("a", later), Ok(()));
+        assert!(limiter.check("a", later).is_err());
+    }
+
+    #[test]
+    fn refill_caps_at_burst() {
+        let limiter = RateLimiter::new(10.0, 2);
+        let start = Instant::now();
+        assert_eq!(limiter.check("a", start), Ok(()));
+        let later = start + Duration::from_secs(60);
+        assert_eq!(limiter.check("a", later), Ok(()));
+        assert_eq!(limiter.check("a", later), Ok(()));
+        assert_eq!(limiter.check("a", later), Err(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn buckets_are_capped_evicting_least_recent() {
+        let limiter = RateLimiter::new(0.001, 1);
+        let start = Instant::now();
+        assert_eq!(limiter.check("first", start), Ok(()));
+        for n in 0..10_000 {
+            let now = start + Duration::from_millis(n + 1);
+            assert_eq!(limiter.check(&n.to_string(), now), Ok(()));
+        }
+        assert_eq!(limiter.len(), 10_000);
+        // Evicted, so it starts over with a full bucket.
+        assert_eq!(limiter.check("first", start + ONE_SECOND * 11), Ok(()));
+        assert!(limiter.check("9999", start + ONE_SECOND * 11).is_err());
+    }
+}
+
+mod batch {
+    use serde_json::json;
+
+    use super::{server::TestServer, *};
+    use crate::{
+        catalog::Catalog,
+        read_rules::{Rules, RulesMap},
+        serve::{recommend_batch, BatchResult},
+    };
+
+    fn rules_map() -> RulesMap {
+        let rules = RuleIndex::from([(
+            vec!["DNA.".into()],
+            ["HUMBLE.".into(), "LOYALTY.".into()].into(),
+        )]);
+        let catalog = Catalog::new(SongCatalog::new(), &rules);
+        RulesMap::new(1708167617000000000, rules, catalog, vec!["ELEMENT.".into()])
+    }
+
+    fn songs(result: &BatchResult) -> Vec<String> {
+        match result {
+            BatchResult::Ok(response) => response.songs.clone(),
+            BatchResult::Error(why) => panic!("Unexpected error: {why}"),
+        }
+    }
+
+    #[test]
+    fn each_request_gets_a_result() {
+        let results = recommend_batch(
+            vec![
+                json!({ "songs": ["DNA."] }),
+                json!({ "songs": ["DNA."], "limit": 1 }),
+                json!({ "songs": "DNA." }),
+                json!({ "songs": ["DNA."], "limit": 0 }),
+                json!({ "songs": [] }),
+                json!({ "songs": ["DNA.", "DNA.", "DNA."] }),
+            ],
+            &rules_map(),
+            2,
+        );
+        assert_eq!(results.len(), 6);
+        assert_eq!(songs(&results[0]), ["HUMBLE.", "LOYALTY.", "ELEMENT."]);
+        assert_eq!(songs(&results[1]), ["HUMBLE."]);
+        assert!(
+            matches!(&results[2], BatchResult::Error(why) if why.starts_with("Invalid request"))
+        );
+        assert!(matches!(&results[3], BatchResult::Error(why) if why.contains("limit")));
+        assert_eq!(songs(&results[4]), ["ELEMENT."]);
+        assert!(matches!(&results[5], BatchResult::Error(why) if why.contains("3 songs")));
+    }
+
+    async fn post_batch(server: &TestServer, batch: serde_json::Value) -> (u16, String, String) {
+        let body = batch.to_string();
+        let request = format!(
+            "POST /api/recommend/batch HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        server.send(&request).await
+    }
+
+    #[tokio::test]
+    async fn route_answers_each_request() {
+        let rules = Rules::fixed(rules_map());
+        let server = TestServer::start(Config::default(), Some(rules)).await;
+        let (status, _, body) =
+            post_batch(&server, json!([{ "songs": ["DNA."] }, { "songs": 1 }])).await;
+        assert_eq!(status, 200, "{body}");
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let results = response["results"].as_array().unwrap();
+        assert_eq!(
+            results[0]["ok"]["songs"],
+            json!(["HUMBLE.", "LOYALTY.", "ELEMENT."])
+        );
+        assert!(results[1]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn route_rejects_oversized_batches() {
+        let config = Config {
+            max_batch_size: 2,
+            max_batch_songs: 3,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, Some(Rules::fixed(rules_map()))).await;
+        let request = json!({ "songs": ["DNA."] });
+        let (status, _, body) = post_batch(&server, json!([request, request, request])).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("3 requests"), "{body}");
+
+        let songs = json!({ "songs": ["DNA.", "DNA."] });
+        let (status, _, body) = post_batch(&server, json!([songs, songs])).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("4 songs"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn single_route_caps_songs() {
+        let config = Config {
+            max_request_songs: 2,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, Some(Rules::fixed(rules_map()))).await;
+        let post = |songs: serde_json::Value| {
+            let body = json!({ "songs": songs }).to_string();
+            format!(
+                "POST /api/recommend HTTP/1.1\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+        };
+        assert_eq!(server.send(&post(json!(["DNA.", "DNA."]))).await.0, 200);
+        let (status, _, body) = server.send(&post(json!(["DNA.", "DNA.", "DNA."]))).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("3 songs"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn route_takes_a_token_per_request() {
+        let config = Config {
+            rate_limit_per_sec: 1.0,
+            rate_limit_burst: 3,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, Some(Rules::fixed(rules_map()))).await;
+        let request = json!({ "songs": ["DNA."] });
+        let batch = json!([request, request, request, request]);
+        assert_eq!(post_batch(&server, batch.clone()).await.0, 200);
+        // Four requests from a burst of three leave the client a token short.
+        let (status, head, _) = post_batch(&server, batch).await;
+        assert_eq!(status, 429);
+        assert!(head.contains("retry-after: 2"), "{head}");
+    }
+}
+
+/// Serving the real routes over TCP.
+mod server {
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::{
+        read_rules::Rules,
+        serve::{app, serve_until_drained},
+    };
+
+    pub struct TestServer {
+        pub address: SocketAddr,
+        pub ready: Arc<AtomicBool>,
+        shutdown: Option<oneshot::Sender<()>>,
+        server: JoinHandle<Result<()>>,
+    }
+
+    impl TestServer {
+        /// Serve on a local port with a data directory of its own,
+        /// answering with `rules` if given, else with the rule server's.
+        pub async fn start(mut config: Config, rules: Option<Rules>) -> Self {
+            static N_SERVERS: AtomicUsize = AtomicUsize::new(0);
+            config.data_dir = temp_dir().join(format!(
+                "rest_server-test-{}-{}",
+                std::process::id(),
+                N_SERVERS.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&config.data_dir).unwrap();
+            let config = Arc::new(config);
+            let health = Arc::new(Health::new(config.retry_policy()));
+            let rule_server = RuleServer::new(
+                config.data_dir.clone(),
+                config.watch_options(),
+                health.clone(),
+            );
+            let rules = rules.unwrap_or_else(|| rule_server.rules());
+            let (_, rule_server_ref) = rule_server.spawn();
+
+            let ready = Arc::new(AtomicBool::new(true));
+            let app = app(
+                config.clone(),
+                health,
+                rules,
+                rule_server_ref,
+                ready.clone(),
+            )
+            .unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let (shutdown, shutdown_receiver) = oneshot::channel();
+            let server = spawn(serve_until_drained(
+                listener,
+                app,
+                ready.clone(),
+                async move { _ = shutdown_receiver.await },
+                config.pre_stop_delay(),
+                config.drain_timeout(),
+            ));
+            Self {
+                address,
+                ready,
+                shutdown: Some(shutdown),
+                server,
+            }
+        }
+
+        /// Send `request`, to which the `Host` and `Connection` headers are
+        /// added, and return the status code, the lowercase head, and the body.
+        pub async fn send(&self, request: &str) -> (u16, String, String) {
+            let (request_line, rest) = request.split_once("\r\n").unwrap();
+            let request = format!("{request_line}\r\nHost: test\r\nConnection: close\r\n{rest}");
+            let mut stream = TcpStream::connect(self.address).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+            (status, head.to_lowercase(), body.into())
+        }
+
+        pub async fn get(&self, path: &str) -> (u16, String, String) {
+            self.send(&format!("GET {path} HTTP/1.1\r\n\r\n")).await
+        }
+
+        /// Start shutting down as on SIGTERM.
+        pub fn shut_down(&mut self) {
+            _ = self.shutdown.take().expect("Shut down once").send(());
+        }
+
+        pub fn is_stopped(&self) -> bool {
+            self.server.is_finished()
+        }
+
+        /// Wait for the server to stop after [`Self::shut_down`].
+        pub async fn stopped(self) {
+            timeout(Duration::from_secs(10), self.server)
+                .await
+                .expect("Server stopped in time")
+                .unwrap()
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn ready_fails_while_still_serving_before_drain() {
+        let config = Config {
+            pre_stop_delay_secs: 1,
+            ..Config::default()
+        };
+        let mut server = TestServer::start(config, None).await;
+        assert_eq!(server.get("/ready").await.0, 200);
+
+        server.shut_down();
+        while server.ready.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let (status, _, body) = server.get("/ready").await;
+        assert_eq!((status, body.as_str()), (503, "shutting down"));
+        assert!(!server.is_stopped());
+        server.stopped().await;
+    }
+
+    async fn post_mine(server: &TestServer, dataset_url: &str) -> u16 {
+        let body = format!(r#"{{"dataset_url":"{dataset_url}"}}"#);
+        let request = format!(
+            "POST /admin/mine HTTP/1.1\r\nAuthorization: Bearer admin-token-0123456\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        server.send(&request).await.0
+    }
+
+    #[tokio::test]
+    async fn mine_rejects_bad_urls_and_jobs_while_shutting_down() {
+        let config = Config {
+            admin_token: Some("admin-token-0123456".into()),
+            pre_stop_delay_secs: 1,
+            ..Config::default()
+        };
+        let mut server = TestServer::start(config, None).await;
+        assert_eq!(post_mine(&server, "file:///etc/passwd").await, 400);
+        assert_eq!(
+            post_mine(&server, "https://example.com/rules.bincode").await,
+            400
+        );
+
+        server.shut_down();
+        while server.ready.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(post_mine(&server, "https://example.com/ds1.csv").await, 503);
+        server.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn status_hides_errors_that_admin_status_reports() {
+        let config = Config {
+            admin_token: Some("admin-token-0123456".into()),
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        let admin_status = "GET /admin/status HTTP/1.1\r\n\
+                            Authorization: Bearer admin-token-0123456\r\n\r\n";
+        // Reading the model fails in the empty data directory.
+        let mut body = String::new();
+        for _ in 0..100 {
+            body = server.send(admin_status).await.2;
+            if body.contains("\"last_error\":\"") {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert!(body.contains("\"last_error\":\""), "{body}");
+
+        let (status, _, body) = server.get("/status").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"consecutive_failures\":"), "{body}");
+        assert!(!body.contains("\"last_error\":\""), "{body}");
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_credential_headers() {
+        let config = Config {
+            cors_origins: vec!["https://a.example".into()],
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        let (status, head, _) = server
+            .send(
+                "OPTIONS /api/recommend HTTP/1.1\r\nOrigin: https://a.example\r\n\
+                 Access-Control-Request-Method: POST\r\n\
+                 Access-Control-Request-Headers: authorization, x-api-key\r\n\r\n",
+            )
+            .await;
+        assert_eq!(status, 200);
+        let allowed = head
+            .lines()
+            .find_map(|line| line.strip_prefix("access-control-allow-headers: "))
+            .unwrap();
+        for header in ["authorization", "content-type", "x-api-key"] {
+            assert!(allowed.contains(header), "{allowed}");
+        }
+    }
+
+    /// A request that the `/api` routes answer without a model.
+    const EMPTY_RECOMMEND: &str = "POST /api/recommend HTTP/1.1\r\n\
+                                   Content-Type: application/json\r\n\
+                                   Content-Length: 2\r\n\r\n{}";
+
+    fn retry_after(head: &str) -> Option<&str> {
+        head.lines()
+            .find_map(|line| line.strip_prefix("retry-after: "))
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_get_retry_after() {
+        let config = Config {
+            rate_limit_per_sec: 0.5,
+            rate_limit_burst: 1,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        assert_eq!(server.send(EMPTY_RECOMMEND).await.0, 422);
+        let (status, head, _) = server.send(EMPTY_RECOMMEND).await;
+        assert_eq!((status, retry_after(&head)), (429, Some("2")));
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_concurrency_cap_are_rejected() {
+        let config = Config {
+            max_concurrent_requests: 1,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        // Holds the only permit while waiting for the rest of its body.
+        let mut slow = TcpStream::connect(server.address).await.unwrap();
+        slow.write_all(
+            b"POST /api/recommend HTTP/1.1\r\nHost: test\r\n\
+              Content-Type: application/json\r\nContent-Length: 100\r\n\r\n{",
+        )
+        .await
+        .unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        let (status, head, _) = server.send(EMPTY_RECOMMEND).await;
+        assert_eq!((status, retry_after(&head)), (429, Some("1")));
+        drop(slow);
+    }
+
+    #[tokio::test]
+    async fn recommend_body_is_limited() {
+        let config = Config {
+            max_recommend_body_bytes: 64,
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        let body = format!(r#"{{"songs":["{}"]}}"#, "a".repeat(64));
+        let request = format!(
+            "POST /api/recommend HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        assert_eq!(server.send(&request).await.0, 413);
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_are_rate_limited() {
+        let config = Config {
+            admin_token: Some("admin-token-0123456".into()),
+            ..Config::default()
+        };
+        let server = TestServer::start(config, None).await;
+        let config_with = |token: &str| {
+            format!("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer {token}\r\n\r\n")
+        };
+        for _ in 0..10 {
+            assert_eq!(server.send(&config_with("guess")).await.0, 401);
+        }
+        let (status, head, _) = server.send(&config_with("guess")).await;
+        assert_eq!((status, retry_after(&head)), (429, Some("10")));
+        // Even the right token waits.
+        let (status, _, _) = server.send(&config_with("admin-token-0123456")).await;
+        assert_eq!(status, 429);
+        // The open `/api` routes are limited separately.
+        assert_eq!(server.send(EMPTY_RECOMMEND).await.0, 422);
+    }
+}
@@ -1,31 +1,140 @@
-use notify::{recommended_watcher, Event, RecommendedWatcher, Watcher};
+use std::{
+    collections::hash_map::DefaultHasher,
+    ffi::OsString,
+    fs,
+    hash::{Hash, Hasher},
+    mem,
+    str::FromStr,
+    time::SystemTime,
+};
 
-use self::read_rules::{RuleServer, RuleServerMsg};
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind},
+    recommended_watcher, Event, EventKind, RecommendedWatcher, Watcher,
+};
+// Tokio's clock, so that tests can pause it.
+use tokio::time::{interval, sleep_until, Instant, MissedTickBehavior};
+
+use self::{
+    read_rules::{RuleServer, RuleServerMsg},
+    retry::Health,
+};
 
 use super::*;
 
 pub struct FileWatcher {
     path: PathBuf,
+    /// Names of the files in `path` whose writes signal a new model.
+    file_names: Vec<OsString>,
     watcher: Option<RecommendedWatcher>,
+    /// Becomes `Poll` when `Auto` falls back to polling.
+    backend: WatchBackend,
+    poll_interval: Duration,
+    /// `None` unless polling.
+    poller: Option<Poller>,
+    debounce: Debounce,
+    health: Arc<Health>,
     server_ref: Ref<RuleServer>,
 }
 
+/// How the file watcher learns about new models.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchBackend {
+    /// Native events, e.g., inotify, falling back to polling when they miss
+    /// a checkpoint change.
+    #[default]
+    Auto,
+    Native,
+    Poll,
+}
+
+impl FromStr for WatchBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "native" => Ok(Self::Native),
+            "poll" => Ok(Self::Poll),
+            _ => bail!("Unknown watch backend `{s}`, expected `auto`, `native`, or `poll`."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    pub backend: WatchBackend,
+    pub debounce: Duration,
+    pub poll_interval: Duration,
+}
+
 impl FileWatcher {
-    pub fn new(path: PathBuf, server_ref: Ref<RuleServer>) -> Self {
+    /// Watch the checkpoint and rules files in the data directory `path`,
+    /// notifying `server_ref` once writes settle.
+    pub fn new(
+        path: PathBuf,
+        options: WatchOptions,
+        health: Arc<Health>,
+        server_ref: Ref<RuleServer>,
+    ) -> Self {
+        let file_names = [checkpoint_path(&path), rules_path(&path)]
+            .iter()
+            .filter_map(|file| file.file_name().map(Into::into))
+            .collect();
         Self {
             path,
+            file_names,
             watcher: None,
+            backend: options.backend,
+            poll_interval: options.poll_interval,
+            poller: None,
+            debounce: Debounce::new(options.debounce),
+            health,
             server_ref,
         }
     }
 
+    fn model_written(&mut self, when: Instant, env: &Ref<Self>) {
+        if self.debounce.event(when) {
+            schedule_settle(env.clone(), when + self.debounce.window);
+        }
+    }
+
+    fn start_polling(&mut self, env: &Ref<Self>) {
+        info!(poll_interval = ?self.poll_interval, "Polling the checkpoint.");
+        self.poller = Some(Poller::new(
+            FileStamp::read(checkpoint_path(&self.path)).ok(),
+        ));
+
+        let mut env = env.clone();
+        let mut ticks = interval(self.poll_interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        drop(spawn(async move {
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                if env.cast(FileWatchEvent::Poll).await.is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+
+    async fn notify_server(&mut self, env: &mut Ref<Self>) {
+        let file_event = RuleServerMsg::WatchedFileChanged(Instant::now().into_std());
+        if self.server_ref.cast(file_event).await.is_err() {
+            warn!("File watcher exiting because the query receiver is closed.");
+            env.cancel();
+        }
+    }
+
     pub async fn try_start_watcher(&mut self, env: &mut Ref<Self>) -> Result<()> {
         let mut event_sender = env.clone();
 
         let mut watcher = recommended_watcher(move |event| {
-            event_sender
-                .blocking_cast(FileWatchEvent::Event(event, Instant::now()))
-                .expect("Failed to send watcher event")
+            // Fails only when the file watcher is shutting down.
+            _ = event_sender.blocking_cast(FileWatchEvent::Event(event, Instant::now()));
         })?;
         watcher.watch(&self.path, notify::RecursiveMode::NonRecursive)?;
 
@@ -40,6 +149,9 @@ impl Actor for FileWatcher {
     type Reply = ();
 
     async fn init(&mut self, env: &mut Ref<Self>) -> Result<()> {
+        if self.backend != WatchBackend::Native {
+            self.start_polling(env);
+        }
         env.cast(FileWatchEvent::Init).await?;
         Ok(())
     }
@@ -48,47 +160,246 @@ impl Actor for FileWatcher {
     async fn handle_cast(&mut self, msg: Self::CastMsg, env: &mut Ref<Self>) -> Result<()> {
         match msg {
             FileWatchEvent::Event(Ok(event), when) => {
+                if !is_model_write(&event, &self.file_names) {
+                    return Ok(());
+                }
                 let (kind, paths) = (event.kind, event.paths);
                 debug!(?kind, ?paths, "File watcher event.");
 
-                let file_event = RuleServerMsg::WatchedFileChanged(when);
-                if self.server_ref.cast(file_event).await.is_err() {
-                    warn!("File watcher exiting because the query receiver is closed.");
-                    env.cancel();
+                if let Some(poller) = &mut self.poller {
+                    poller.native_event();
                 }
+                self.model_written(when, env);
             }
             FileWatchEvent::Event(Err(why), _) => {
-                error!(?why, "Received file watcher error. Restarting watcher");
+                error!(
+                    ?why,
+                    "Received file watcher error, restarting watcher after backoff."
+                );
 
-                let mut env = env.clone();
-                drop(spawn(
-                    async move { _ = env.cast(FileWatchEvent::Init).await },
-                ));
+                let (mut env, health) = (env.clone(), self.health.clone());
+                let why = why.into();
+                drop(spawn(async move {
+                    health.backoff("file_watcher", &why).await;
+                    _ = env.cast(FileWatchEvent::Init).await
+                }));
+            }
+            FileWatchEvent::Poll => {
+                let Some(poller) = &mut self.poller else {
+                    return Ok(());
+                };
+                let stamp = FileStamp::read(checkpoint_path(&self.path)).ok();
+                match poller.poll(stamp, self.watcher.is_some()) {
+                    PollOutcome::Unchanged => {}
+                    PollOutcome::Changed => {
+                        debug!("Polling found a checkpoint change.");
+                        self.model_written(Instant::now(), env);
+                    }
+                    PollOutcome::NativeSilent => {
+                        warn!("Native file events missed a checkpoint change, falling back to polling.");
+                        self.backend = WatchBackend::Poll;
+                        self.watcher = None;
+                        self.model_written(Instant::now(), env);
+                    }
+                }
             }
+            FileWatchEvent::Settle => match self.debounce.settle(Instant::now()) {
+                Settled::Fire => self.notify_server(env).await,
+                Settled::WaitUntil(deadline) => schedule_settle(env.clone(), deadline),
+                Settled::Idle => {}
+            },
 
+            FileWatchEvent::Init if self.backend == WatchBackend::Poll => {}
             FileWatchEvent::Init => {
                 info!("Initializing file watcher.");
 
-                if let Err(why) = self.try_start_watcher(env).await {
-                    error!(
-                        ?why,
-                        "Failed to initialize watcher, restarting after sleep."
-                    );
-
-                    let mut env = env.clone();
-                    drop(spawn(async move {
-                        sleep(ONE_SECOND).await;
-                        _ = env.cast(FileWatchEvent::Init).await
-                    }));
+                match self.try_start_watcher(env).await {
+                    Ok(()) => self.health.succeeded("file_watcher"),
+                    Err(why) if self.backend == WatchBackend::Auto => {
+                        warn!(
+                            ?why,
+                            "Failed to initialize watcher, falling back to polling."
+                        );
+                        self.backend = WatchBackend::Poll;
+                    }
+                    Err(why) => {
+                        error!(
+                            ?why,
+                            "Failed to initialize watcher, restarting after backoff."
+                        );
+
+                        let (mut env, health) = (env.clone(), self.health.clone());
+                        drop(spawn(async move {
+                            health.backoff("file_watcher", &why).await;
+                            _ = env.cast(FileWatchEvent::Init).await
+                        }));
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    async fn before_exit(&mut self, _env: &mut Ref<Self>) -> Result<()> {
+        // Dropping the watcher stops its thread.
+        self.watcher = None;
+        Ok(())
+    }
 }
 
 pub enum FileWatchEvent {
     Event(notify::Result<Event>, Instant),
+    /// A debounce deadline passed.
+    Settle,
+    /// Time to poll the checkpoint.
+    Poll,
     Init,
 }
+
+fn schedule_settle(mut env: Ref<FileWatcher>, deadline: Instant) {
+    drop(spawn(async move {
+        sleep_until(deadline).await;
+        _ = env.cast(FileWatchEvent::Settle).await
+    }));
+}
+
+/// Whether `event` writes one of the files named `file_names`,
+/// ignoring reads, metadata changes, and removals.
+pub fn is_model_write(event: &Event, file_names: &[OsString]) -> bool {
+    let is_write = matches!(
+        event.kind,
+        EventKind::Any
+            | EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_))
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    );
+    is_write
+        && event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| file_names.iter().any(|file_name| file_name == name))
+        })
+}
+
+/// Coalesces bursts of events into one, fired once no event arrived for
+/// `window`.
+#[derive(Debug)]
+pub struct Debounce {
+    pub window: Duration,
+    deadline: Option<Instant>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Settled {
+    /// The burst is over.
+    Fire,
+    /// Events arrived since the timer started; wait until the new deadline.
+    WaitUntil(Instant),
+    /// No burst is pending.
+    Idle,
+}
+
+impl Debounce {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            deadline: None,
+        }
+    }
+
+    /// Record an event at `when`,
+    /// returning whether a timer for the new burst must be started.
+    pub fn event(&mut self, when: Instant) -> bool {
+        let starts_burst = self.deadline.is_none();
+        let deadline = when + self.window;
+        self.deadline = Some(self.deadline.map_or(deadline, |old| old.max(deadline)));
+        starts_burst
+    }
+
+    /// Check the burst when a timer expires at `now`.
+    pub fn settle(&mut self, now: Instant) -> Settled {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.deadline = None;
+                Settled::Fire
+            }
+            Some(deadline) => Settled::WaitUntil(deadline),
+            None => Settled::Idle,
+        }
+    }
+}
+
+/// Modification time, size, and content hash of a file,
+/// which changes with each write even where the modification time is coarse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl FileStamp {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let metadata = fs::metadata(&path)?;
+        let mut hasher = DefaultHasher::new();
+        fs::read(&path)?.hash(&mut hasher);
+        Ok(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: hasher.finish(),
+        })
+    }
+}
+
+/// Tracks the checkpoint between polls,
+/// to catch changes that native file events missed.
+#[derive(Debug)]
+pub struct Poller {
+    /// `None` while there is no checkpoint.
+    stamp: Option<FileStamp>,
+    native_seen: bool,
+    /// The last poll found a change without a native event,
+    /// which may still arrive if the poll raced the write.
+    unconfirmed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PollOutcome {
+    /// Unchanged, or the change was already reported by native events.
+    Unchanged,
+    Changed,
+    /// The native watcher is running but reported nothing for a change
+    /// by the poll after it.
+    NativeSilent,
+}
+
+impl Poller {
+    pub fn new(stamp: Option<FileStamp>) -> Self {
+        Self {
+            stamp,
+            native_seen: false,
+            unconfirmed: false,
+        }
+    }
+
+    /// Record a native event on a model file since the last poll.
+    pub fn native_event(&mut self) {
+        self.native_seen = true;
+    }
+
+    pub fn poll(&mut self, stamp: Option<FileStamp>, native_active: bool) -> PollOutcome {
+        let native_seen = mem::take(&mut self.native_seen);
+        let unconfirmed = mem::take(&mut self.unconfirmed);
+        let changed = stamp != self.stamp;
+        self.stamp = stamp;
+        match (native_active, native_seen) {
+            (false, _) if changed || unconfirmed => PollOutcome::Changed,
+            (true, false) if unconfirmed => PollOutcome::NativeSilent,
+            (true, false) if changed => {
+                self.unconfirmed = true;
+                PollOutcome::Unchanged
+            }
+            _ => PollOutcome::Unchanged,
+        }
+    }
+}
@@ -1,10 +1,24 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::File,
-    io::Read,
+    io::{BufReader, BufWriter, Read},
     path::{Path, PathBuf},
 };
 
+pub use model::{
+    decode_rules, encode_rules, read_rules, write_rules, ArtifactError, Checkpoint, Model,
+    ModelMetadata, RulesFileFormat, LEGACY_ITEM_COLUMN, LEGACY_TRANSACTION_COLUMN,
+    RULES_FORMAT_VERSION, RULES_MAGIC,
+};
+pub use recommend::{index_rules, recommend, RuleIndex, Source};
+
+mod model;
+mod recommend;
+#[cfg(test)]
+mod tests;
+
 pub const MAX_LENGTH: usize = 8;
 
 /// Copied from <https://docs.rs/clap/latest/clap/macro.crate_version.html>.
@@ -23,6 +37,108 @@ pub fn checkpoint_path(data_dir: impl AsRef<Path>) -> PathBuf {
     data_dir.as_ref().join("ml_processor_checkpoint.txt")
 }
 
+pub fn metadata_path(data_dir: impl AsRef<Path>) -> PathBuf {
+    data_dir.as_ref().join("model_metadata.json")
+}
+
+pub fn catalog_path(data_dir: impl AsRef<Path>) -> PathBuf {
+    data_dir.as_ref().join("song_catalog.json")
+}
+
+/// Display information of a mined item, e.g., a track URI.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Song {
+    pub name: String,
+    pub artist: Option<String>,
+    /// Number of transactions containing the item.
+    pub count: usize,
+}
+
+/// Maps each mined item to its display information.
+pub type SongCatalog = HashMap<String, Song>;
+
+pub fn write_catalog(catalog: &SongCatalog, path: impl AsRef<Path>) -> Result<()> {
+    write_json(catalog, path).context("Failed to write song catalog")
+}
+
+pub fn read_catalog(path: impl AsRef<Path>) -> Result<SongCatalog> {
+    read_json(path).context("Failed to read song catalog")
+}
+
+pub fn frequencies_path(data_dir: impl AsRef<Path>) -> PathBuf {
+    data_dir.as_ref().join("item_frequencies.json")
+}
+
+/// Global support counts of all mined items.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemFrequencies {
+    pub n_transactions: usize,
+    /// Items with the number of transactions containing them,
+    /// most frequent first.
+    pub counts: Vec<(String, usize)>,
+}
+
+pub fn write_frequencies(frequencies: &ItemFrequencies, path: impl AsRef<Path>) -> Result<()> {
+    write_json(frequencies, path).context("Failed to write item frequencies")
+}
+
+pub fn read_frequencies(path: impl AsRef<Path>) -> Result<ItemFrequencies> {
+    read_json(path).context("Failed to read item frequencies")
+}
+
+pub fn itemsets_path(data_dir: impl AsRef<Path>) -> PathBuf {
+    data_dir.as_ref().join("frequent_itemsets.json")
+}
+
+/// Thresholds the rules were mined with.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MiningParameters {
+    /// Minimum fraction of transactions containing an itemset.
+    pub min_support: f32,
+    pub min_confidence: f32,
+    /// Maximum number of items in an itemset.
+    pub max_length: usize,
+}
+
+/// Support counts of the frequent items and of every itemset in the rules,
+/// enough to recompute rule metrics without re-mining.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrequentItemsets {
+    pub n_transactions: usize,
+    pub parameters: MiningParameters,
+    /// Sorted itemsets with the number of transactions containing them,
+    /// most frequent first.
+    pub counts: Vec<(Vec<String>, usize)>,
+}
+
+impl FrequentItemsets {
+    pub fn count_map(&self) -> HashMap<&[String], usize> {
+        self.counts
+            .iter()
+            .map(|(items, count)| (items.as_slice(), *count))
+            .collect()
+    }
+}
+
+pub fn write_itemsets(itemsets: &FrequentItemsets, path: impl AsRef<Path>) -> Result<()> {
+    write_json(itemsets, path).context("Failed to write frequent itemsets")
+}
+
+pub fn read_itemsets(path: impl AsRef<Path>) -> Result<FrequentItemsets> {
+    read_json(path).context("Failed to read frequent itemsets")
+}
+
+fn write_json(value: &impl Serialize, path: impl AsRef<Path>) -> Result<()> {
+    let file = File::create(&path).with_context(|| format!("Create {:?}", path.as_ref()))?;
+    serde_json::to_writer(BufWriter::new(file), value)?;
+    Ok(())
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: impl AsRef<Path>) -> Result<T> {
+    let file = File::open(&path).with_context(|| format!("Open {:?}", path.as_ref()))?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
 pub fn read_file(path: impl AsRef<Path>) -> Result<String> {
     let mut file = File::open(path)?;
     let mut content = String::new();
@@ -0,0 +1,317 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use apriori::Rule;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{checkpoint_path, metadata_path, rules_path, MiningParameters};
+
+/// Leads versioned rules files, followed by the format version as a
+/// little-endian `u32` and the bincode-encoded rules.
+pub const RULES_MAGIC: &[u8; 8] = b"ARRULES\0";
+/// Version of the rules file format written by [`write_rules`].
+pub const RULES_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ArtifactError {
+    #[error("Failed to access {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to encode or decode rules")]
+    Bincode(#[from] bincode::Error),
+    #[error("Failed to encode or decode {path:?}")]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Rules format version {0} is newer than the supported {RULES_FORMAT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Invalid checkpoint: {0}")]
+    InvalidCheckpoint(String),
+}
+
+type Result<T, E = ArtifactError> = std::result::Result<T, E>;
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> ArtifactError + '_ {
+    move |source| ArtifactError::Io {
+        path: path.into(),
+        source,
+    }
+}
+
+/// Like [`io_error`], for the I/O errors bincode wraps.
+fn bincode_error(path: &Path) -> impl FnOnce(bincode::Error) -> ArtifactError + '_ {
+    move |error| match *error {
+        bincode::ErrorKind::Io(source) => io_error(path)(source),
+        error => ArtifactError::Bincode(error.into()),
+    }
+}
+
+/// Layout of a rules file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RulesFileFormat {
+    /// A bare bincode `Vec<Rule>`, as written before versioning.
+    Legacy,
+    Versioned(u32),
+}
+
+impl RulesFileFormat {
+    /// Detect the format from the start of a rules file.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes.strip_prefix(RULES_MAGIC) {
+            Some(rest) if rest.len() >= 4 => {
+                Self::Versioned(u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]))
+            }
+            _ => Self::Legacy,
+        }
+    }
+}
+
+/// Encode rules in the versioned format;
+/// I/O errors of `writer` are returned as [`bincode::ErrorKind::Io`].
+pub fn encode_rules(rules: &[Rule], mut writer: impl Write) -> bincode::Result<()> {
+    writer.write_all(RULES_MAGIC)?;
+    writer.write_all(&RULES_FORMAT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(writer, rules)
+}
+
+/// Decode rules in either format.
+pub fn decode_rules(bytes: &[u8]) -> Result<Vec<Rule>> {
+    match RulesFileFormat::detect(bytes) {
+        RulesFileFormat::Legacy => Ok(bincode::deserialize(bytes)?),
+        RulesFileFormat::Versioned(RULES_FORMAT_VERSION) => {
+            let payload = &bytes[RULES_MAGIC.len() + 4..];
+            Ok(bincode::deserialize(payload)?)
+        }
+        RulesFileFormat::Versioned(version) => Err(ArtifactError::UnsupportedVersion(version)),
+    }
+}
+
+pub fn write_rules(rules: &[Rule], path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path).map_err(io_error(path))?;
+    let mut writer = BufWriter::new(file);
+    encode_rules(rules, &mut writer).map_err(bincode_error(path))?;
+    writer.flush().map_err(io_error(path))
+}
+
+pub fn read_rules(path: impl AsRef<Path>) -> Result<Vec<Rule>> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|file| BufReader::new(file).read_to_end(&mut bytes))
+        .map_err(io_error(path))?;
+    decode_rules(&bytes)
+}
+
+/// Describes how the rules were mined, written next to them as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub ml_processor_version: String,
+    pub dataset_url: String,
+    pub transaction_column: String,
+    pub item_columns: Vec<String>,
+    pub item_separator: String,
+    pub n_transactions: usize,
+    pub n_rules: usize,
+    /// `None` for imported rules.
+    pub parameters: Option<MiningParameters>,
+    /// Hex SHA-256 of the downloaded dataset file, to tell if the dataset
+    /// at an unchanged URL changed; `None` for imported rules and
+    /// models mined before it was recorded.
+    #[serde(default)]
+    pub dataset_sha256: Option<String>,
+}
+
+impl ModelMetadata {
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(io_error(path))?;
+        serde_json::to_writer_pretty(file, self).map_err(|source| ArtifactError::Json {
+            path: path.into(),
+            source,
+        })
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(io_error(path))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|source| ArtifactError::Json {
+            path: path.into(),
+            source,
+        })
+    }
+}
+
+/// Configuration and time of the last successful mining, as one line:
+///
+/// ```xml
+/// <ML processor version> <dataset URL> <timestamp in nanoseconds since UNIX epoch> <transaction column> <comma-separated item columns>
+/// ```
+///
+/// Checkpoints written before the columns were recorded end at the timestamp
+/// and are read with [`LEGACY_TRANSACTION_COLUMN`] and [`LEGACY_ITEM_COLUMN`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub ml_processor_version: String,
+    pub dataset_url: String,
+    pub timestamp: i64,
+    pub transaction_column: String,
+    pub item_columns: Vec<String>,
+}
+
+/// Transaction column of checkpoints that do not record it.
+pub const LEGACY_TRANSACTION_COLUMN: &str = "pid";
+/// Item column of checkpoints that do not record it,
+/// which versions before the configurable columns mined on.
+pub const LEGACY_ITEM_COLUMN: &str = "track_name";
+
+impl Checkpoint {
+    /// A checkpoint timestamped now.
+    pub fn new(
+        ml_processor_version: &str,
+        dataset_url: &str,
+        transaction_column: &str,
+        item_columns: &[String],
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Current time is later than UNIX epoch")
+            .as_nanos() as i64;
+        Self {
+            ml_processor_version: ml_processor_version.into(),
+            dataset_url: dataset_url.into(),
+            timestamp,
+            transaction_column: transaction_column.into(),
+            item_columns: item_columns.to_vec(),
+        }
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        File::create(path)
+            .and_then(|mut file| writeln!(file, "{self}"))
+            .map_err(io_error(path))
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(io_error(path))?;
+        content.parse()
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.ml_processor_version,
+            self.dataset_url,
+            self.timestamp,
+            self.transaction_column,
+            self.item_columns.join(",")
+        )
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = ArtifactError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut splits = s.split_whitespace();
+        let mut next = |field: &str| {
+            splits
+                .next()
+                .ok_or_else(|| ArtifactError::InvalidCheckpoint(format!("No {field}")))
+        };
+        let ml_processor_version = next("ML processor version")?.into();
+        let dataset_url = next("dataset URL")?.into();
+        let timestamp = next("timestamp")?;
+        let timestamp = timestamp.parse().map_err(|_| {
+            ArtifactError::InvalidCheckpoint(format!("Invalid timestamp `{timestamp}`"))
+        })?;
+        let transaction_column = splits.next().unwrap_or(LEGACY_TRANSACTION_COLUMN).into();
+        let item_columns = splits
+            .next()
+            .unwrap_or(LEGACY_ITEM_COLUMN)
+            .split(',')
+            .map(Into::into)
+            .collect();
+        Ok(Self {
+            ml_processor_version,
+            dataset_url,
+            timestamp,
+            transaction_column,
+            item_columns,
+        })
+    }
+}
+
+/// The artifacts of one mining run in a data directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+    pub rules: Vec<Rule>,
+    /// `None` for data directories written before metadata existed.
+    pub metadata: Option<ModelMetadata>,
+    pub checkpoint: Checkpoint,
+}
+
+impl Model {
+    pub fn read(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let checkpoint = Checkpoint::read(checkpoint_path(&data_dir))?;
+        let rules = read_rules(rules_path(&data_dir))?;
+        let metadata_path = metadata_path(&data_dir);
+        let metadata = match metadata_path.exists() {
+            true => Some(ModelMetadata::read(metadata_path)?),
+            false => None,
+        };
+        Ok(Self {
+            rules,
+            metadata,
+            checkpoint,
+        })
+    }
+
+    /// Write the rules and metadata, then the checkpoint,
+    /// which signals the REST API Server that a new model is ready.
+    /// Each file is replaced whole, so readers never see it half-written.
+    pub fn write(&self, data_dir: impl AsRef<Path>) -> Result<()> {
+        replace_file(&rules_path(&data_dir), |path| {
+            write_rules(&self.rules, path)
+        })?;
+        if let Some(metadata) = &self.metadata {
+            replace_file(&metadata_path(&data_dir), |path| metadata.write(path))?;
+        }
+        replace_file(&checkpoint_path(&data_dir), |path| {
+            self.checkpoint.write(path)
+        })
+    }
+}
+
+/// Write a temporary file next to `path` with `write`,
+/// then rename it over `path`.
+fn replace_file(path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    if let Err(why) = write(&temp_path) {
+        _ = fs::remove_file(&temp_path);
+        return Err(why);
+    }
+    fs::rename(&temp_path, path).map_err(io_error(path))
+}
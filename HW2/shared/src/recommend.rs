@@ -0,0 +1,79 @@
+use std::collections::{HashMap, HashSet};
+
+use apriori::Rule;
+use itertools::Itertools;
+use serde::Serialize;
+
+use super::MAX_LENGTH;
+
+/// Maps each sorted antecedent to the union of its consequents.
+pub type RuleIndex = HashMap<Vec<String>, HashSet<String>>;
+
+pub fn index_rules(rules: impl IntoIterator<Item = Rule>) -> RuleIndex {
+    let mut index = RuleIndex::new();
+    for Rule {
+        antecedent,
+        consequent,
+        ..
+    } in rules
+    {
+        let mut antecedent: Vec<_> = antecedent.into_iter().collect();
+        antecedent.sort_unstable();
+        index.entry(antecedent).or_default().extend(consequent);
+    }
+    index
+}
+
+/// Where a recommendation came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Rules,
+    /// The most popular songs fill in slots the rules leave empty.
+    Popularity,
+}
+
+/// Recommend songs for `query` from the rules whose antecedents are
+/// the largest subsets of the query, then fill up to [`MAX_LENGTH`] songs
+/// from `popular_items`, most popular first.
+/// The order is deterministic: longer antecedents first, then by item.
+pub fn recommend(
+    mut query: Vec<String>,
+    rules: &RuleIndex,
+    popular_items: &[String],
+) -> Vec<(String, Source)> {
+    query.sort_unstable();
+    query.dedup();
+    let mut seen = HashSet::with_capacity(MAX_LENGTH * 2);
+    let mut songs = Vec::with_capacity(MAX_LENGTH * 2);
+
+    'combinations: for length in (1..(query.len().min(MAX_LENGTH) + 1)).rev() {
+        for combination in query.iter().cloned().combinations(length) {
+            if let Some(predictions) = rules.get(&combination) {
+                let mut predictions: Vec<&String> = predictions
+                    .iter()
+                    .filter(|song| !seen.contains(song))
+                    .collect();
+                predictions.sort_unstable();
+                for song in predictions {
+                    seen.insert(song);
+                    songs.push((song.clone(), Source::Rules));
+                }
+                if songs.len() >= MAX_LENGTH {
+                    break 'combinations;
+                }
+            }
+        }
+    }
+
+    if songs.len() < MAX_LENGTH {
+        let n_missing = MAX_LENGTH - songs.len();
+        let popular_songs = popular_items
+            .iter()
+            .filter(|song| !seen.contains(song) && query.binary_search(song).is_err())
+            .take(n_missing)
+            .map(|song| (song.clone(), Source::Popularity));
+        songs.extend(popular_songs);
+    }
+    songs
+}
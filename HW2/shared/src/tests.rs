@@ -0,0 +1,120 @@
+use std::{env::temp_dir, fs};
+
+use apriori::Rule;
+
+use super::*;
+
+fn rules() -> Vec<Rule> {
+    vec![Rule {
+        antecedent: ["DNA.".into(), "HUMBLE.".into()].into(),
+        consequent: ["LOYALTY.".into()].into(),
+        confidence: 0.75,
+        lift: 1.5,
+    }]
+}
+
+#[test]
+fn rules_round_trip() {
+    let mut bytes = Vec::new();
+    encode_rules(&rules(), &mut bytes).unwrap();
+    assert_eq!(
+        RulesFileFormat::detect(&bytes),
+        RulesFileFormat::Versioned(RULES_FORMAT_VERSION)
+    );
+    assert_eq!(decode_rules(&bytes).unwrap(), rules());
+}
+
+#[test]
+fn read_legacy_rules() {
+    let bytes = bincode::serialize(&rules()).unwrap();
+    assert_eq!(RulesFileFormat::detect(&bytes), RulesFileFormat::Legacy);
+    assert_eq!(decode_rules(&bytes).unwrap(), rules());
+}
+
+#[test]
+fn reject_newer_rules_format() {
+    let mut bytes = RULES_MAGIC.to_vec();
+    bytes.extend((RULES_FORMAT_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        decode_rules(&bytes),
+        Err(ArtifactError::UnsupportedVersion(version)) if version == RULES_FORMAT_VERSION + 1
+    ));
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let line = "0.1.2 https://example.com/ds1.csv 1708167617000000000 pid track_uri,album_uri";
+    let checkpoint: Checkpoint = line.parse().unwrap();
+    assert_eq!(checkpoint.timestamp, 1708167617000000000);
+    assert_eq!(checkpoint.item_columns, ["track_uri", "album_uri"]);
+    assert_eq!(checkpoint.to_string(), line);
+
+    assert!(matches!(
+        "0.1.2 https://example.com/ds1.csv".parse::<Checkpoint>(),
+        Err(ArtifactError::InvalidCheckpoint(_))
+    ));
+}
+
+#[test]
+fn read_legacy_checkpoint_without_columns() {
+    let checkpoint: Checkpoint = "0.1.1 https://example.com/ds1.csv 1708167617000000000"
+        .parse()
+        .unwrap();
+    assert_eq!(checkpoint.dataset_url, "https://example.com/ds1.csv");
+    assert_eq!(checkpoint.transaction_column, "pid");
+    assert_eq!(checkpoint.item_columns, ["track_name"]);
+}
+
+#[test]
+fn write_errors_while_encoding_report_the_path() {
+    // Larger than the write buffer, so that encoding itself fails to write.
+    let mut rules = rules();
+    rules[0].consequent = ["x".repeat(64 * 1024)].into();
+    assert!(matches!(
+        write_rules(&rules, "/dev/full"),
+        Err(ArtifactError::Io { path, .. }) if path == Path::new("/dev/full")
+    ));
+}
+
+#[test]
+fn model_round_trip() {
+    let data_dir = temp_dir().join(format!("shared-test-{}", std::process::id()));
+    fs::create_dir_all(&data_dir).unwrap();
+    let item_columns = ["track_uri".to_owned()];
+    let model = Model {
+        rules: rules(),
+        metadata: Some(ModelMetadata {
+            ml_processor_version: "0.1.2".into(),
+            dataset_url: "https://example.com/ds1.csv".into(),
+            transaction_column: "pid".into(),
+            item_columns: item_columns.to_vec(),
+            item_separator: " – ".into(),
+            n_transactions: 2,
+            n_rules: 1,
+            parameters: None,
+            dataset_sha256: Some("ab12".into()),
+        }),
+        checkpoint: Checkpoint::new("0.1.2", "https://example.com/ds1.csv", "pid", &item_columns),
+    };
+    model.write(&data_dir).unwrap();
+    assert_eq!(Model::read(&data_dir).unwrap(), model);
+}
+
+#[test]
+fn index_rules_unions_consequents_of_the_same_antecedent() {
+    let rule = |antecedent: [&str; 2], consequent: &str| Rule {
+        antecedent: antecedent.map(Into::into).into(),
+        consequent: [consequent.into()].into(),
+        confidence: 0.8,
+        lift: 1.0,
+    };
+    let index = index_rules([
+        rule(["DNA.", "HUMBLE."], "LOYALTY."),
+        rule(["HUMBLE.", "DNA."], "ELEMENT."),
+    ]);
+    assert_eq!(index.len(), 1);
+    assert_eq!(
+        index[&vec!["DNA.".to_owned(), "HUMBLE.".to_owned()]],
+        ["ELEMENT.".to_owned(), "LOYALTY.".to_owned()].into()
+    );
+}